@@ -1,41 +1,775 @@
-use clap::Parser;
+use clap::{ArgAction, ArgGroup, Args, Parser};
+use encoding_rs::Encoding;
+use enum_dispatch::enum_dispatch;
 use std::fmt;
 use std::str::FromStr;
-use crate::CmdExector;
+use crate::{process_csv_decrypt, process_csv_diff, process_csv_encrypt, process_csv_fake, process_csv_mask, process_csv_merge, process_csv_query, process_csv_show, process_csv_split, process_csv_stats, process_csv_validate, CmdExector, Expr, COMPRESSED_EXTENSIONS};
 use super::verify_file;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Outputformat {
     Json,
+    Ndjson,
     Yaml,
+    Toml,
+    Xml,
+    Markdown,
+    Html,
+    Csv,
+    Xlsx,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inputformat {
+    Csv,
+    Json,
+    Ndjson,
+    Yaml,
+    Toml,
+    Xlsx,
+    /// Fixed-width text, laid out by `--layout`
+    Fixed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    Terminal,
+    Json,
+    Csv,
+}
+
+/// Where `csv split` starts a new part.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitBy {
+    Rows(usize),
+    /// Parts stay under this many bytes, unless a single row is larger
+    Bytes(u64),
+    /// One part per value of the column
+    Column(String),
+}
+
+/// How `csv mask` hides the values of a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaskStrategy {
+    Redact,
+    /// Keep the first N characters
+    Truncate(usize),
+    /// Keep only the year of a date
+    Year,
+    /// Replace with a token keyed by `--key`, the same value always gets the same token
+    Pseudonymize,
+}
+
+/// What `rcli csv` does with a row that can't be read or typed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    Fail,
+    Skip,
+    /// Skip it, writing it to the reject file
+    Quarantine,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvTrim {
+    None,
+    Headers,
+    Fields,
+    All,
+}
+
+/// `rcli csv -i ...` converts, like `rcli csv convert -i ...` does. The fields of
+/// `CsvSource` are repeated here as clap can't tell whether an `Option` of a
+/// flattened struct with nested flattened structs was given.
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct CsvOpts {
+    #[command(subcommand)]
+    pub cmd: Option<CsvSubCommand>,
+
+    #[arg(short, long, value_parser = verify_file, required = true)]
+    pub input: Option<String>,
+
+    #[arg(long, value_parser = parse_input_format)]
+    pub from: Option<Inputformat>,
+
+    #[command(flatten)]
+    pub dialect: CsvDialect,
+
+    #[command(flatten)]
+    pub types: CsvTypes,
+
+    #[command(flatten)]
+    pub pipeline: CsvPipeline,
+}
+
+#[derive(Debug, Parser)]
+#[enum_dispatch(CmdExector)]
+#[allow(clippy::large_enum_variant)]
+pub enum CsvSubCommand {
+    #[command(name = "convert", about = "Convert CSV to other formats (and back)")]
+    Convert(CsvConvertOpts),
+    #[command(name = "show", about = "Show CSV as a table in the terminal")]
+    Show(CsvShowOpts),
+    #[command(name = "query", about = "Query CSV files with SQL")]
+    Query(CsvQueryOpts),
+    #[command(name = "stats", about = "Profile the columns of a CSV file")]
+    Stats(CsvStatsOpts),
+    #[command(name = "validate", about = "Check a CSV file against a schema")]
+    Validate(CsvValidateOpts),
+    #[command(name = "diff", about = "Compare two versions of a CSV file by key")]
+    Diff(CsvDiffOpts),
+    #[command(name = "mask", about = "Redact or pseudonymize columns before sharing a file")]
+    Mask(CsvMaskOpts),
+    #[command(name = "encrypt", about = "Encrypt the cells of some columns with a shared key")]
+    Encrypt(CsvEncryptOpts),
+    #[command(name = "decrypt", about = "Decrypt the cells encrypted by csv encrypt")]
+    Decrypt(CsvDecryptOpts),
+    #[command(name = "split", about = "Split a CSV file by rows, size or column value")]
+    Split(CsvSplitOpts),
+    #[command(name = "merge", about = "Concatenate files into one with the union of their columns")]
+    Merge(CsvMergeOpts),
+    #[command(name = "fake", about = "Generate test data from a schema")]
+    Fake(CsvFakeOpts),
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvConvertOpts {
+    #[command(flatten)]
+    pub source: CsvSource,
+
+    #[command(flatten)]
+    pub pipeline: CsvPipeline,
+}
+
+/// What happens to records between reading and writing them.
+#[derive(Debug, Clone, Args)]
+pub struct CsvPipeline {
+    /// Output file, `-` for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub out: OutputOpts,
+
+    #[command(flatten)]
+    pub filter: CsvFilter,
+
+    /// Rename, retype, fill, derive and drop columns as set in a TOML or JSON file
+    #[arg(long, value_parser = verify_file)]
+    pub mapping: Option<String>,
+
+    /// Threads parsing and typing CSV input, all cores by default
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+
+    /// Show a progress bar with rows/sec on stderr
+    #[arg(long)]
+    pub progress: bool,
+
+    /// What to do with a row that can't be read, typed or mapped: fail, skip or quarantine
+    #[arg(long, value_parser = parse_on_error, default_value = "fail")]
+    pub on_error: OnError,
+
+    /// Where `--on-error quarantine` writes rejected rows: line number, reason and the fields re-serialized as CSV
+    #[arg(long, default_value = "rejects.csv")]
+    pub reject_file: String,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct CsvFilter {
+    /// Only keep these columns, in this order
+    #[arg(long, value_delimiter = ',')]
+    pub select: Vec<String>,
+
+    /// Drop these columns
+    #[arg(long, value_delimiter = ',')]
+    pub exclude: Vec<String>,
+
+    /// Only keep rows matching an expression, e.g. `Position = 'Goalkeeper' and "Kit Number" < 30`
+    #[arg(long = "where", value_parser = parse_where)]
+    pub filter: Option<Expr>,
+
+    /// Sort by columns as COLUMN[:asc|desc][:num|lex], e.g. `--sort-by "Kit Number:desc,Name"`
+    #[arg(long, value_delimiter = ',', value_parser = parse_sort_key)]
+    pub sort_by: Vec<SortKey>,
+
+    /// Group rows by these columns, giving one record per group
+    #[arg(long, value_delimiter = ',')]
+    pub group_by: Vec<String>,
+
+    /// Aggregates per group: count, count(COL), sum(COL), avg(COL), min(COL), max(COL)
+    #[arg(long, value_delimiter = ',', value_parser = parse_agg)]
+    pub agg: Vec<AggSpec>,
+}
+
+/// An aggregate of `--agg`, `count` alone counts rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggSpec {
+    pub func: AggFunc,
+    pub column: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggFunc {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortKey {
+    pub column: String,
+    pub desc: bool,
+    pub mode: SortMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortMode {
+    Auto,
+    Numeric,
+    Lexical,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvShowOpts {
+    #[command(flatten)]
+    pub source: CsvSource,
+
+    /// Skip this many rows first
+    #[arg(long, default_value_t = 0)]
+    pub offset: usize,
+
+    /// Show only the first N rows (after the offset)
+    #[arg(long, conflicts_with = "tail")]
+    pub head: Option<usize>,
+
+    /// Show only the last N rows
+    #[arg(long)]
+    pub tail: Option<usize>,
+
+    /// Truncate cells wider than this
+    #[arg(long, default_value_t = 40)]
+    pub max_width: usize,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvQueryOpts {
+    /// e.g. "SELECT Nationality, count(*) FROM 'assets/juventus.csv' GROUP BY Nationality ORDER BY 2 DESC"
+    pub sql: String,
+
+    /// Output file, `-` for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub out: OutputOpts,
+
+    #[command(flatten)]
+    pub dialect: CsvDialect,
+
+    #[command(flatten)]
+    pub types: CsvTypes,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvStatsOpts {
+    #[command(flatten)]
+    pub source: CsvSource,
+
+    /// Number of most frequent values listed per column
+    #[arg(long, default_value_t = 5)]
+    pub top: usize,
+
+    /// Report as a terminal table or JSON
+    #[arg(long, value_parser = parse_stats_format, default_value = "table")]
+    pub format: StatsFormat,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvValidateOpts {
+    #[command(flatten)]
+    pub source: CsvSource,
+
+    /// Schema file (TOML or JSON) declaring columns, types, required flags, patterns, enums and ranges
+    #[arg(long, value_parser = verify_file)]
+    pub schema: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvDiffOpts {
+    #[arg(value_parser = verify_file)]
+    pub old: String,
+
+    #[arg(value_parser = verify_file)]
+    pub new: String,
+
+    /// Columns identifying a row in both files
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub key: Vec<String>,
+
+    /// Colored terminal view, JSON report, or a CSV patch with an `_op` column
+    #[arg(long, value_parser = parse_diff_format, default_value = "terminal")]
+    pub format: DiffFormat,
+
+    /// Output file, `-` for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub dialect: CsvDialect,
+
+    #[command(flatten)]
+    pub types: CsvTypes,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvMaskOpts {
+    #[command(flatten)]
+    pub source: CsvSource,
+
+    /// Mask a column: `DOB=year`, `Name=pseudonymize`, `Notes=redact` or `Name=truncate:3`
+    #[arg(long = "mask", value_parser = parse_mask_rule, required = true)]
+    pub masks: Vec<(String, MaskStrategy)>,
+
+    /// Blake3 key file for pseudonymization, as written by `text generate`
+    #[arg(long, value_parser = verify_file)]
+    pub key: Option<String>,
+
+    /// Output file, `-` for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub out: OutputOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvEncryptOpts {
+    #[command(flatten)]
+    pub source: CsvSource,
+
+    /// Columns whose cells are encrypted, comma separated or repeated
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub fields: Vec<String>,
+
+    /// 32 byte ChaCha20-Poly1305 key file
+    #[arg(long, value_parser = verify_file)]
+    pub key: String,
+
+    /// Output file, `-` for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub out: OutputOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvDecryptOpts {
+    #[command(flatten)]
+    pub source: CsvSource,
+
+    /// Columns whose cells are decrypted, comma separated or repeated
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub fields: Vec<String>,
+
+    /// 32 byte ChaCha20-Poly1305 key file
+    #[arg(long, value_parser = verify_file)]
+    pub key: String,
+
+    /// Output file, `-` for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub out: OutputOpts,
+}
+
+#[derive(Debug, Parser)]
+#[command(group = ArgGroup::new("split_by").required(true).args(["rows", "bytes", "by"]))]
+pub struct CsvSplitOpts {
+    #[command(flatten)]
+    pub source: CsvSource,
+
+    /// Rows per part
+    #[arg(long)]
+    pub rows: Option<usize>,
+
+    /// Maximum size of a part, in bytes or with a K, M or G suffix
+    #[arg(long, value_parser = parse_size)]
+    pub bytes: Option<u64>,
+
+    /// Write one part per value of this column
+    #[arg(long)]
+    pub by: Option<String>,
+
+    /// Directory the parts are written to
+    #[arg(long, default_value = ".")]
+    pub dir: String,
+
+    /// Start of the part names, the input file name by default
+    #[arg(long)]
+    pub prefix: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvMergeOpts {
+    /// Files to concatenate, columns missing from a file are left empty
+    #[arg(value_parser = verify_file, required = true)]
+    pub inputs: Vec<String>,
+
+    /// Output file, `-` for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub out: OutputOpts,
+
+    #[command(flatten)]
+    pub dialect: CsvDialect,
+
+    #[command(flatten)]
+    pub types: CsvTypes,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvFakeOpts {
+    /// Schema file (TOML or JSON) with the name and kind of each column
+    #[arg(long, value_parser = verify_file)]
+    pub schema: String,
+
+    /// Number of rows to generate
+    #[arg(long, default_value_t = 100)]
+    pub rows: usize,
+
+    /// Seed of the random generator, the same seed gives the same data
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Output file, `-` for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub out: OutputOpts,
+}
+
+/// Where records are read from and how they are parsed.
+#[derive(Debug, Clone, Args)]
+pub struct CsvSource {
     #[arg(short, long, value_parser = verify_file)]
     pub input: String,
-    
-    #[arg(short, long)]
-    pub output: Option<String>,
-
-    #[arg(long, value_parser = parse_format, default_value = "json")]
-    pub format: Outputformat,
-    
-    #[arg(short, long, default_value_t = ',')]
-    pub delimiter: char,
-    
-    #[arg(long, default_value_t = true)]
+
+    /// Input format, detected from the file extension when not set
+    #[arg(long, value_parser = parse_input_format)]
+    pub from: Option<Inputformat>,
+
+    #[command(flatten)]
+    pub dialect: CsvDialect,
+
+    #[command(flatten)]
+    pub types: CsvTypes,
+}
+
+/// Which format records are written in, and how they are wrapped.
+#[derive(Debug, Clone, Args)]
+pub struct OutputOpts {
+    /// Output format, detected from the output file extension and JSON otherwise
+    #[arg(long, value_parser = parse_format)]
+    pub format: Option<Outputformat>,
+
+    /// Name of the XML root element and of the TOML array of tables
+    #[arg(long, default_value = "rows")]
+    pub root: String,
+
+    /// Name of the XML element wrapping each record
+    #[arg(long, default_value = "row")]
+    pub row: String,
+
+    /// Field delimiter of CSV output, defaults to the input delimiter
+    #[arg(long, value_parser = parse_delimiter)]
+    pub out_delimiter: Option<u8>,
+
+    /// Write an object keyed by the `--group-by` values instead of a list (json, yaml, toml)
+    #[arg(long)]
+    pub keyed: bool,
+
+    /// Rebuild nested objects and arrays from headers like `address.city` and `tags[0]`
+    #[arg(long, conflicts_with = "flatten")]
+    pub unflatten: bool,
+
+    /// Spread nested objects and arrays over `address.city` and `tags[0]` columns
+    #[arg(long)]
+    pub flatten: bool,
+
+    /// Compress the output with gzip, implied by a `.gz` output file
+    #[arg(long, conflicts_with = "zstd")]
+    pub gzip: bool,
+
+    /// Compress the output with zstd, implied by a `.zst` output file
+    #[arg(long)]
+    pub zstd: bool,
+
+    /// Character encoding of CSV output, e.g. `windows-1252` or `utf-16le`, UTF-8 by default
+    #[arg(long, value_parser = parse_encoding)]
+    pub output_encoding: Option<&'static Encoding>,
+}
+
+/// How the input CSV is laid out: separators, quoting, comments and headers.
+#[derive(Debug, Clone, Args)]
+pub struct CsvDialect {
+    /// Field delimiter, a single ASCII character (`\t` or `tab` for TSV)
+    #[arg(short, long, value_parser = parse_delimiter, default_value = ",")]
+    pub delimiter: u8,
+
+    /// Whether the first row holds the column names
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
     pub header: bool,
+
+    /// Quote character
+    #[arg(long, value_parser = parse_ascii_char, default_value = "\"")]
+    pub quote: u8,
+
+    /// Escape character for quotes, doubled quotes are used when not set
+    #[arg(long, value_parser = parse_ascii_char)]
+    pub escape: Option<u8>,
+
+    /// Lines starting with this character are skipped
+    #[arg(long, value_parser = parse_ascii_char)]
+    pub comment: Option<u8>,
+
+    /// Trim whitespace around headers, fields or both
+    #[arg(long, value_parser = parse_trim, default_value = "none")]
+    pub trim: CsvTrim,
+
+    /// Allow rows with a different number of fields than the header
+    #[arg(long)]
+    pub flexible: bool,
+
+    /// Column names to use instead of (or in absence of) the header row
+    #[arg(long, value_delimiter = ',')]
+    pub columns: Vec<String>,
+
+    /// Character encoding of the input, e.g. `windows-1252` or `utf-16le`. Detected
+    /// from the BOM or the first bytes when not set
+    #[arg(long, value_parser = parse_encoding)]
+    pub encoding: Option<&'static Encoding>,
+
+    /// Worksheet of an xlsx input, the first one when not set
+    #[arg(long)]
+    pub sheet: Option<String>,
+
+    /// Column layout (TOML or JSON) of a fixed-width input, which it implies
+    #[arg(long, value_parser = verify_file)]
+    pub layout: Option<String>,
+}
+
+/// How field values are typed in the output.
+#[derive(Debug, Clone, Args)]
+pub struct CsvTypes {
+    /// Keep every field as a string instead of inferring types
+    #[arg(long = "no-infer", action = ArgAction::SetFalse)]
+    pub infer: bool,
+
+    /// Number of rows sampled to infer column types
+    #[arg(long, default_value_t = 100)]
+    pub sample_rows: usize,
+
+    /// Force a column type, e.g. `--type "Kit Number=int"`. Dates may be followed by more text, like `Apr 18, 1990 (29)`
+    #[arg(long = "type", value_parser = parse_type_override)]
+    pub overrides: Vec<(String, ColumnType)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    String,
+    Int,
+    Float,
+    Bool,
+    Date,
+}
+
+impl Default for CsvTypes {
+    fn default() -> Self {
+        Self {
+            infer: true,
+            sample_rows: 100,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl Default for OutputOpts {
+    fn default() -> Self {
+        Self {
+            format: None,
+            root: "rows".to_string(),
+            row: "row".to_string(),
+            out_delimiter: None,
+            keyed: false,
+            unflatten: false,
+            flatten: false,
+            gzip: false,
+            zstd: false,
+            output_encoding: None,
+        }
+    }
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            header: true,
+            quote: b'"',
+            escape: None,
+            comment: None,
+            trim: CsvTrim::None,
+            flexible: false,
+            columns: Vec::new(),
+            encoding: None,
+            sheet: None,
+            layout: None,
+        }
+    }
+}
+
+impl OutputOpts {
+    /// `--format` if given, else guessed from the output file name, else JSON.
+    pub fn format(&self, output: Option<&str>) -> Outputformat {
+        self.format
+            .or_else(|| output.and_then(Outputformat::from_path))
+            .unwrap_or(Outputformat::Json)
+    }
+}
+
+impl CsvSource {
+    pub fn format(&self) -> Inputformat {
+        match (self.from, &self.dialect.layout) {
+            (Some(format), _) => format,
+            (None, Some(_)) => Inputformat::Fixed,
+            (None, None) => Inputformat::from_path(&self.input),
+        }
+    }
 }
 
 impl CmdExector for CsvOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let output = if let Some(output) = &self.output {
-            output.to_string()
-        }else {
-            format!("output.{}", self.format)
+        match (self.cmd, self.input) {
+            (Some(cmd), _) => cmd.execute().await,
+            (None, Some(input)) => {
+                let source = CsvSource { input, from: self.from, dialect: self.dialect, types: self.types };
+                let convert = CsvConvertOpts { source, pipeline: self.pipeline };
+                convert.execute().await
+            }
+            (None, None) => Err(anyhow::anyhow!("Missing --input or subcommand")),
+        }
+    }
+}
+
+impl CmdExector for CsvConvertOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        crate::process_csv(&self.source, self.pipeline.output.clone(), &self.pipeline)
+    }
+}
+
+impl CmdExector for CsvShowOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let table = process_csv_show(&self.source, self.offset, self.head, self.tail, self.max_width)?;
+        print!("{}", table);
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvQueryOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        process_csv_query(&self.sql, &self.dialect, &self.types, &self.output, &self.out)
+    }
+}
+
+impl CmdExector for CsvStatsOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let report = process_csv_stats(&self.source, self.top, self.format)?;
+        print!("{}", report);
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvValidateOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let violations = process_csv_validate(&self.source, &self.schema)?;
+        for violation in &violations {
+            println!("{}", violation);
+        }
+        match violations.len() {
+            0 => Ok(()),
+            n => Err(anyhow::anyhow!("{} found {} violation(s) of {}", self.source.input, n, self.schema)),
+        }
+    }
+}
+
+impl CmdExector for CsvDiffOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let source = |input: String| CsvSource { input, from: None, dialect: self.dialect.clone(), types: self.types.clone() };
+        process_csv_diff(&source(self.old.clone()), &source(self.new.clone()), &self.key, self.format, &self.output)
+    }
+}
+
+impl CmdExector for CsvMaskOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        process_csv_mask(&self.source, &self.masks, self.key.as_deref(), &self.output, &self.out)
+    }
+}
+
+impl CmdExector for CsvEncryptOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        process_csv_encrypt(&self.source, &self.fields, &self.key, &self.output, &self.out)
+    }
+}
+
+impl CmdExector for CsvDecryptOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        process_csv_decrypt(&self.source, &self.fields, &self.key, &self.output, &self.out)
+    }
+}
+
+impl CmdExector for CsvSplitOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let by = match (self.rows, self.bytes, self.by) {
+            (Some(rows), _, _) => SplitBy::Rows(rows),
+            (_, Some(bytes), _) => SplitBy::Bytes(bytes),
+            (_, _, Some(column)) => SplitBy::Column(column),
+            _ => unreachable!("clap requires one of --rows, --bytes and --by"),
         };
-        crate::process_csv(&self.input, output, self.format)
+        for part in process_csv_split(&self.source, &by, &self.dir, self.prefix.as_deref())? {
+            println!("{}", part);
+        }
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvMergeOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let sources: Vec<CsvSource> = self
+            .inputs
+            .into_iter()
+            .map(|input| CsvSource { input, from: None, dialect: self.dialect.clone(), types: self.types.clone() })
+            .collect();
+        process_csv_merge(&sources, &self.output, &self.out)
+    }
+}
+
+impl CmdExector for CsvFakeOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        process_csv_fake(&self.schema, self.rows, self.seed, &self.output, &self.out)
     }
 }
 
@@ -43,11 +777,96 @@ fn parse_format(format: &str) -> Result<Outputformat, anyhow::Error> {
     format.parse::<Outputformat>()
 }
 
+fn parse_input_format(format: &str) -> Result<Inputformat, anyhow::Error> {
+    format.parse::<Inputformat>()
+}
+
+fn parse_where(expr: &str) -> Result<Expr, anyhow::Error> {
+    expr.parse::<Expr>()
+}
+
+fn parse_sort_key(key: &str) -> Result<SortKey, anyhow::Error> {
+    key.parse::<SortKey>()
+}
+
+fn parse_stats_format(format: &str) -> Result<StatsFormat, anyhow::Error> {
+    format.parse::<StatsFormat>()
+}
+
+fn parse_diff_format(format: &str) -> Result<DiffFormat, anyhow::Error> {
+    format.parse::<DiffFormat>()
+}
+
+fn parse_agg(agg: &str) -> Result<AggSpec, anyhow::Error> {
+    agg.parse::<AggSpec>()
+}
+
+fn parse_on_error(on_error: &str) -> Result<OnError, anyhow::Error> {
+    on_error.parse::<OnError>()
+}
+
+fn parse_trim(trim: &str) -> Result<CsvTrim, anyhow::Error> {
+    trim.parse::<CsvTrim>()
+}
+
+fn parse_type_override(s: &str) -> Result<(String, ColumnType), anyhow::Error> {
+    let (column, ty) = s
+        .rsplit_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected COLUMN=TYPE"))?;
+    Ok((column.to_string(), ty.parse()?))
+}
+
+fn parse_mask_rule(s: &str) -> Result<(String, MaskStrategy), anyhow::Error> {
+    let (column, strategy) = s
+        .rsplit_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected COLUMN=STRATEGY"))?;
+    Ok((column.to_string(), strategy.parse()?))
+}
+
+fn parse_size(size: &str) -> Result<u64, anyhow::Error> {
+    let upper = size.trim().to_ascii_uppercase();
+    let (digits, unit) = match upper.strip_suffix('B').unwrap_or(&upper) {
+        s if s.ends_with('K') => (&s[..s.len() - 1], 1 << 10),
+        s if s.ends_with('M') => (&s[..s.len() - 1], 1 << 20),
+        s if s.ends_with('G') => (&s[..s.len() - 1], 1 << 30),
+        s => (s, 1),
+    };
+    match digits.trim().parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n * unit),
+        _ => Err(anyhow::anyhow!("Invalid size {:?}, e.g. 500000, 64K or 10M", size)),
+    }
+}
+
+fn parse_delimiter(delimiter: &str) -> Result<u8, anyhow::Error> {
+    match delimiter {
+        "\\t" | "tab" => Ok(b'\t'),
+        _ => parse_ascii_char(delimiter),
+    }
+}
+
+fn parse_encoding(label: &str) -> Result<&'static Encoding, anyhow::Error> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| anyhow::anyhow!("Unknown encoding {:?}", label))
+}
+
+fn parse_ascii_char(c: &str) -> Result<u8, anyhow::Error> {
+    match c.as_bytes() {
+        [b] if b.is_ascii() => Ok(*b),
+        _ => Err(anyhow::anyhow!("Expected a single ASCII character")),
+    }
+}
+
 impl From<Outputformat> for &'static str {
     fn from(format: Outputformat) -> Self {
         match format {
             Outputformat::Json => "json",
+            Outputformat::Ndjson => "ndjson",
             Outputformat::Yaml => "yaml",
+            Outputformat::Toml => "toml",
+            Outputformat::Xml => "xml",
+            Outputformat::Markdown => "md",
+            Outputformat::Html => "html",
+            Outputformat::Csv => "csv",
+            Outputformat::Xlsx => "xlsx",
         }
     }
 }
@@ -58,7 +877,14 @@ impl FromStr for Outputformat {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "json" => Ok(Outputformat::Json),
-            "yaml" => Ok(Outputformat::Yaml),
+            "ndjson" | "jsonl" => Ok(Outputformat::Ndjson),
+            "yaml" | "yml" => Ok(Outputformat::Yaml),
+            "toml" => Ok(Outputformat::Toml),
+            "xml" => Ok(Outputformat::Xml),
+            "markdown" | "md" => Ok(Outputformat::Markdown),
+            "html" => Ok(Outputformat::Html),
+            "csv" => Ok(Outputformat::Csv),
+            "xlsx" => Ok(Outputformat::Xlsx),
             _ => Err(anyhow::anyhow!("Invalid format")),
         }
     }
@@ -68,4 +894,494 @@ impl fmt::Display for Outputformat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", Into::<&str>::into(*self))
     }
-} 
\ No newline at end of file
+}
+
+impl Inputformat {
+    /// Guess the format from the file extension, looking past `.gz` and the like.
+    /// Anything unknown (and stdin) is CSV.
+    pub fn from_path(path: &str) -> Self {
+        format_extension(path)
+            .and_then(|ext| ext.parse().ok())
+            .unwrap_or(Inputformat::Csv)
+    }
+}
+
+impl Outputformat {
+    /// Guess the format from the file extension, looking past `.gz` and the like.
+    pub fn from_path(path: &str) -> Option<Self> {
+        match format_extension(path)?.as_str() {
+            "tsv" => Some(Outputformat::Csv),
+            ext => ext.parse().ok(),
+        }
+    }
+}
+
+/// The lowercased extension naming the format of `path`, skipping a compression extension.
+pub fn format_extension(path: &str) -> Option<String> {
+    let path = std::path::Path::new(path);
+    let path = match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if COMPRESSED_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()) => path.with_extension(""),
+        _ => path.to_path_buf(),
+    };
+    path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase())
+}
+
+impl From<Inputformat> for &'static str {
+    fn from(format: Inputformat) -> Self {
+        match format {
+            Inputformat::Csv => "csv",
+            Inputformat::Json => "json",
+            Inputformat::Ndjson => "ndjson",
+            Inputformat::Yaml => "yaml",
+            Inputformat::Toml => "toml",
+            Inputformat::Xlsx => "xlsx",
+            Inputformat::Fixed => "fixed",
+        }
+    }
+}
+
+impl FromStr for Inputformat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" | "tsv" => Ok(Inputformat::Csv),
+            "json" => Ok(Inputformat::Json),
+            "ndjson" | "jsonl" => Ok(Inputformat::Ndjson),
+            "yaml" | "yml" => Ok(Inputformat::Yaml),
+            "toml" => Ok(Inputformat::Toml),
+            "xlsx" => Ok(Inputformat::Xlsx),
+            "fixed" => Ok(Inputformat::Fixed),
+            _ => Err(anyhow::anyhow!("Invalid input format")),
+        }
+    }
+}
+
+impl fmt::Display for Inputformat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let column = parts.next().unwrap_or_default().trim();
+        if column.is_empty() {
+            return Err(anyhow::anyhow!("Missing sort column"));
+        }
+        let mut key = SortKey {
+            column: column.to_string(),
+            desc: false,
+            mode: SortMode::Auto,
+        };
+        for part in parts {
+            match part.trim() {
+                "asc" => key.desc = false,
+                "desc" => key.desc = true,
+                "num" | "numeric" => key.mode = SortMode::Numeric,
+                "lex" | "lexical" => key.mode = SortMode::Lexical,
+                other => return Err(anyhow::anyhow!("Invalid sort option {:?}", other)),
+            }
+        }
+        Ok(key)
+    }
+}
+
+impl FromStr for AggSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (func, column) = match s.split_once('(') {
+            Some((func, rest)) => {
+                let column = rest
+                    .strip_suffix(')')
+                    .ok_or_else(|| anyhow::anyhow!("Missing ) in {:?}", s))?
+                    .trim();
+                (func.trim(), Some(column).filter(|c| *c != "*"))
+            }
+            None => (s, None),
+        };
+        let func: AggFunc = func.to_ascii_lowercase().parse()?;
+        if column.is_none() && func != AggFunc::Count {
+            return Err(anyhow::anyhow!("{} needs a column, e.g. {}(Kit Number)", func, func));
+        }
+        Ok(AggSpec { func, column: column.map(String::from) })
+    }
+}
+
+impl fmt::Display for AggSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.column {
+            Some(column) => write!(f, "{}({})", self.func, column),
+            None => write!(f, "{}", self.func),
+        }
+    }
+}
+
+impl From<AggFunc> for &'static str {
+    fn from(func: AggFunc) -> Self {
+        match func {
+            AggFunc::Count => "count",
+            AggFunc::Sum => "sum",
+            AggFunc::Avg => "avg",
+            AggFunc::Min => "min",
+            AggFunc::Max => "max",
+        }
+    }
+}
+
+impl FromStr for AggFunc {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "count" => Ok(AggFunc::Count),
+            "sum" => Ok(AggFunc::Sum),
+            "avg" | "mean" => Ok(AggFunc::Avg),
+            "min" => Ok(AggFunc::Min),
+            "max" => Ok(AggFunc::Max),
+            _ => Err(anyhow::anyhow!("Invalid aggregate {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for AggFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<StatsFormat> for &'static str {
+    fn from(format: StatsFormat) -> Self {
+        match format {
+            StatsFormat::Table => "table",
+            StatsFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for StatsFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(StatsFormat::Table),
+            "json" => Ok(StatsFormat::Json),
+            _ => Err(anyhow::anyhow!("Invalid stats format")),
+        }
+    }
+}
+
+impl fmt::Display for StatsFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<DiffFormat> for &'static str {
+    fn from(format: DiffFormat) -> Self {
+        match format {
+            DiffFormat::Terminal => "terminal",
+            DiffFormat::Json => "json",
+            DiffFormat::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for DiffFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "terminal" => Ok(DiffFormat::Terminal),
+            "json" => Ok(DiffFormat::Json),
+            "csv" => Ok(DiffFormat::Csv),
+            _ => Err(anyhow::anyhow!("Invalid diff format")),
+        }
+    }
+}
+
+impl fmt::Display for DiffFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl FromStr for MaskStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("truncate", n)) => n
+                .parse()
+                .map(MaskStrategy::Truncate)
+                .map_err(|_| anyhow::anyhow!("Invalid truncate length {:?}", n)),
+            Some(_) => Err(anyhow::anyhow!("Invalid mask {:?}", s)),
+            None => match s {
+                "redact" => Ok(MaskStrategy::Redact),
+                "year" => Ok(MaskStrategy::Year),
+                "pseudonymize" => Ok(MaskStrategy::Pseudonymize),
+                "truncate" => Err(anyhow::anyhow!("truncate needs a length, e.g. truncate:3")),
+                _ => Err(anyhow::anyhow!("Invalid mask {:?}", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for MaskStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaskStrategy::Redact => write!(f, "redact"),
+            MaskStrategy::Truncate(n) => write!(f, "truncate:{}", n),
+            MaskStrategy::Year => write!(f, "year"),
+            MaskStrategy::Pseudonymize => write!(f, "pseudonymize"),
+        }
+    }
+}
+
+impl From<OnError> for &'static str {
+    fn from(on_error: OnError) -> Self {
+        match on_error {
+            OnError::Fail => "fail",
+            OnError::Skip => "skip",
+            OnError::Quarantine => "quarantine",
+        }
+    }
+}
+
+impl FromStr for OnError {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(OnError::Fail),
+            "skip" => Ok(OnError::Skip),
+            "quarantine" => Ok(OnError::Quarantine),
+            _ => Err(anyhow::anyhow!("Invalid --on-error mode, expected fail, skip or quarantine")),
+        }
+    }
+}
+
+impl fmt::Display for OnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<CsvTrim> for &'static str {
+    fn from(trim: CsvTrim) -> Self {
+        match trim {
+            CsvTrim::None => "none",
+            CsvTrim::Headers => "headers",
+            CsvTrim::Fields => "fields",
+            CsvTrim::All => "all",
+        }
+    }
+}
+
+impl FromStr for CsvTrim {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(CsvTrim::None),
+            "headers" => Ok(CsvTrim::Headers),
+            "fields" => Ok(CsvTrim::Fields),
+            "all" => Ok(CsvTrim::All),
+            _ => Err(anyhow::anyhow!("Invalid trim mode")),
+        }
+    }
+}
+
+impl fmt::Display for CsvTrim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl From<ColumnType> for &'static str {
+    fn from(ty: ColumnType) -> Self {
+        match ty {
+            ColumnType::String => "string",
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Bool => "bool",
+            ColumnType::Date => "date",
+        }
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "string" | "str" => Ok(ColumnType::String),
+            "int" => Ok(ColumnType::Int),
+            "float" => Ok(ColumnType::Float),
+            "bool" => Ok(ColumnType::Bool),
+            "date" => Ok(ColumnType::Date),
+            _ => Err(anyhow::anyhow!("Invalid column type")),
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_delimiter() {
+        assert_eq!(parse_delimiter(",").unwrap(), b',');
+        assert_eq!(parse_delimiter(";").unwrap(), b';');
+        assert_eq!(parse_delimiter("\\t").unwrap(), b'\t');
+        assert_eq!(parse_delimiter("tab").unwrap(), b'\t');
+        assert!(parse_delimiter(",;").is_err());
+        assert!(parse_delimiter("é").is_err());
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(parse_encoding("UTF-16LE").unwrap(), encoding_rs::UTF_16LE);
+        assert_eq!(parse_encoding("cp1252").unwrap(), encoding_rs::WINDOWS_1252);
+        assert!(parse_encoding("ebcdic").is_err());
+    }
+
+    #[test]
+    fn test_csv_opts_command() {
+        use clap::CommandFactory;
+        CsvOpts::command().debug_assert();
+    }
+
+    #[test]
+    fn test_csv_opts_dialect() {
+        let opts = CsvConvertOpts::parse_from([
+            "csv", "-i", "-", "-d", ";", "--header", "false", "--columns", "a,b", "--encoding", "latin1",
+        ]);
+        assert_eq!(opts.source.dialect.delimiter, b';');
+        assert_eq!(opts.source.dialect.encoding, Some(encoding_rs::WINDOWS_1252));
+        assert!(!opts.source.dialect.header);
+        assert_eq!(opts.source.dialect.columns, vec!["a", "b"]);
+        assert!(opts.source.types.infer);
+    }
+
+    #[test]
+    fn test_csv_opts_encrypt() {
+        let opts = CsvOpts::parse_from(["csv", "encrypt", "-i", "assets/juventus.csv", "--fields", "DOB,Nationality", "--key", "Cargo.toml"]);
+        match opts.cmd {
+            Some(CsvSubCommand::Encrypt(opts)) => {
+                assert_eq!(opts.fields, vec!["DOB", "Nationality"]);
+                assert!(opts.source.dialect.columns.is_empty());
+            }
+            _ => panic!("expected csv encrypt"),
+        }
+    }
+
+    #[test]
+    fn test_csv_opts_subcommands() {
+        let opts = CsvOpts::parse_from(["csv", "-i", "assets/juventus.csv", "--format", "yaml"]);
+        assert!(opts.cmd.is_none());
+        assert_eq!(opts.input.as_deref(), Some("assets/juventus.csv"));
+        assert!(matches!(opts.pipeline.out.format, Some(Outputformat::Yaml)));
+
+        let opts = CsvOpts::parse_from(["csv", "show", "-i", "assets/juventus.csv", "--tail", "3"]);
+        assert!(matches!(opts.cmd, Some(CsvSubCommand::Show(CsvShowOpts { tail: Some(3), .. }))));
+        assert!(CsvOpts::try_parse_from(["csv", "show", "-i", "-", "--head", "1", "--tail", "1"]).is_err());
+        assert!(CsvOpts::try_parse_from(["csv", "--format", "yaml"]).is_err());
+    }
+
+    #[test]
+    fn test_input_format_from_path() {
+        assert_eq!(Inputformat::from_path("assets/juventus.csv"), Inputformat::Csv);
+        assert_eq!(Inputformat::from_path("players.JSON"), Inputformat::Json);
+        assert_eq!(Inputformat::from_path("players.jsonl"), Inputformat::Ndjson);
+        assert_eq!(Inputformat::from_path("players.yml"), Inputformat::Yaml);
+        assert_eq!(Inputformat::from_path("-"), Inputformat::Csv);
+        assert_eq!(Inputformat::from_path("players.json.gz"), Inputformat::Json);
+        assert_eq!(Inputformat::from_path("players.csv.zst"), Inputformat::Csv);
+        assert_eq!(Inputformat::from_path("Roster.XLSX"), Inputformat::Xlsx);
+    }
+
+    #[test]
+    fn test_output_format() {
+        let out = OutputOpts::default();
+        assert_eq!(out.format(Some("players.yml.gz")), Outputformat::Yaml);
+        assert_eq!(out.format(Some("players.tsv")), Outputformat::Csv);
+        assert_eq!(out.format(Some("players.xlsx")), Outputformat::Xlsx);
+        assert_eq!(out.format(Some("-")), Outputformat::Json);
+        assert_eq!(out.format(None), Outputformat::Json);
+        let out = OutputOpts { format: Some(Outputformat::Xml), ..Default::default() };
+        assert_eq!(out.format(Some("players.yml")), Outputformat::Xml);
+        assert!(CsvConvertOpts::try_parse_from(["convert", "-i", "-", "--gzip", "--zstd"]).is_err());
+        assert_eq!(CsvConvertOpts::parse_from(["convert", "-i", "-", "--gzip"]).pipeline.output, "-");
+    }
+
+    #[test]
+    fn test_agg_spec() {
+        assert_eq!("count".parse::<AggSpec>().unwrap(), AggSpec { func: AggFunc::Count, column: None });
+        assert_eq!("count(*)".parse::<AggSpec>().unwrap(), AggSpec { func: AggFunc::Count, column: None });
+        let min = "MIN( DOB )".parse::<AggSpec>().unwrap();
+        assert_eq!(min, AggSpec { func: AggFunc::Min, column: Some("DOB".into()) });
+        assert_eq!(min.to_string(), "min(DOB)");
+        assert!("sum".parse::<AggSpec>().is_err());
+        assert!("median(DOB)".parse::<AggSpec>().is_err());
+        assert!("max(DOB".parse::<AggSpec>().is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("500").unwrap(), 500);
+        assert_eq!(parse_size("64K").unwrap(), 64 * 1024);
+        assert_eq!(parse_size("10mb").unwrap(), 10 * 1024 * 1024);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("ten").is_err());
+    }
+
+    #[test]
+    fn test_mask_rule() {
+        assert_eq!(parse_mask_rule("DOB=year").unwrap(), ("DOB".to_string(), MaskStrategy::Year));
+        assert_eq!(parse_mask_rule("Name=truncate:3").unwrap(), ("Name".to_string(), MaskStrategy::Truncate(3)));
+        assert_eq!(MaskStrategy::Truncate(3).to_string(), "truncate:3");
+        assert!(parse_mask_rule("Name=truncate").is_err());
+        assert!(parse_mask_rule("Name=hash").is_err());
+        assert!(parse_mask_rule("Name").is_err());
+    }
+
+    #[test]
+    fn test_sort_key() {
+        assert_eq!(
+            "Kit Number:desc:num".parse::<SortKey>().unwrap(),
+            SortKey { column: "Kit Number".into(), desc: true, mode: SortMode::Numeric }
+        );
+        assert_eq!(
+            "Name".parse::<SortKey>().unwrap(),
+            SortKey { column: "Name".into(), desc: false, mode: SortMode::Auto }
+        );
+        assert!("Name:up".parse::<SortKey>().is_err());
+        assert!(":desc".parse::<SortKey>().is_err());
+    }
+
+    #[test]
+    fn test_type_override() {
+        let opts = CsvConvertOpts::parse_from([
+            "csv", "-i", "-", "--no-infer", "--type", "Kit Number=int", "--type", "DOB=date",
+        ]);
+        assert!(!opts.source.types.infer);
+        assert_eq!(
+            opts.source.types.overrides,
+            vec![("Kit Number".to_string(), ColumnType::Int), ("DOB".to_string(), ColumnType::Date)]
+        );
+        assert!(parse_type_override("Kit Number").is_err());
+        assert!(parse_type_override("Kit Number=number").is_err());
+    }
+}
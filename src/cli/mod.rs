@@ -26,6 +26,7 @@ pub struct Opts {
 
 #[derive(Debug, Parser)]
 #[enum_dispatch(CmdExector)]
+#[allow(clippy::large_enum_variant)]
 pub enum SubCommand {
     #[command(name = "csv", about = "Show csv or convert CSV to other formats")]
     Csv(CsvOpts),
@@ -43,9 +44,9 @@ pub enum SubCommand {
 
 fn verify_file(filename: &str) -> Result<String, &'static str> {
     if filename == "-" || Path::new(filename).exists() {
-        return Ok(filename.into());
+        Ok(filename.into())
     } else {
-        return Err("File does not exist!");
+        Err("File does not exist!")
     }
 }
 
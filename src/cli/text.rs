@@ -106,7 +106,7 @@ impl From<TextSignFormat> for &'static str {
 
 impl fmt::Display for TextSignFormat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", *self)
+        write!(f, "{}", Into::<&str>::into(*self))
     }
 }
 
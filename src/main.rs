@@ -1,3 +1,5 @@
+use std::io;
+
 use clap::Parser;
 use rcli::{CmdExector, Opts};
 
@@ -5,6 +7,25 @@ use rcli::{CmdExector, Opts};
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
     let opts = Opts::parse();
-    opts.cmd.execute().await?;
-    Ok(())
+    match opts.cmd.execute().await {
+        // whoever reads stdout stopped early, like `| head`
+        Err(e) if is_broken_pipe(&e) => Ok(()),
+        result => result,
+    }
+}
+
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let kind = if let Some(e) = cause.downcast_ref::<csv::Error>() {
+            match e.kind() {
+                csv::ErrorKind::Io(e) => Some(e.kind()),
+                _ => None,
+            }
+        } else if let Some(e) = cause.downcast_ref::<serde_json::Error>() {
+            e.io_error_kind()
+        } else {
+            cause.downcast_ref::<io::Error>().map(io::Error::kind)
+        };
+        kind == Some(io::ErrorKind::BrokenPipe)
+    })
 }
@@ -26,8 +26,8 @@ pub fn process_decode(input: &str, format: Base64Format) -> anyhow::Result<Vec<u
     let buf = buf.trim();
 
     let decoded = match format {
-        Base64Format::Standard => STANDARD.decode(&buf)?,
-        Base64Format::UrlSafe => URL_SAFE_NO_PAD.decode(&buf)?,
+        Base64Format::Standard => STANDARD.decode(buf)?,
+        Base64Format::UrlSafe => URL_SAFE_NO_PAD.decode(buf)?,
     };
     
     Ok(decoded)
@@ -1,43 +1,337 @@
-use serde::{Serialize, Deserialize};
-use csv::Reader;
-use std::fs;
+use csv::{ByteRecord, Reader, ReaderBuilder, StringRecord, Trim};
+use serde_json::{Map, Value};
+use std::{cell::RefCell, io::{BufWriter, Read}, rc::Rc, thread};
 use anyhow::Result;
 
-use crate::cli::Outputformat;
+use crate::{cli::{format_extension, CsvDialect, CsvPipeline, CsvSource, CsvTrim, CsvTypes, Inputformat, OutputOpts}, decompress, read_input};
+use super::{csv_encoding::decode_input, csv_filter::apply_filter, csv_fixed::fixed_records, csv_infer::TypedColumns, csv_mapping::{map_records, Mapping}, csv_nested::reshape_records, csv_parallel::parallel_csv_records, csv_progress::Progress, csv_reject::{recover_records, RejectedRecord, Rejects}, csv_structured::structured_records, csv_xlsx::xlsx_records, csv_writer::{keyed_writer, output_writer, record_writer}};
 
+pub type Record = Map<String, Value>;
 
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-struct Player {
-    name: String, 
-    #[serde(rename = "Position")]
-    positon: String,
-    #[serde(rename = "DOB")]
-    dob: String,
-    nationality: String,
-    #[serde(rename = "Kit Number")]
-    kit: u8, 
+/// Headers plus the records of an input, produced lazily where the format allows it.
+pub struct RecordStream<'a> {
+    pub headers: Vec<String>,
+    /// Columns typed as dates, their values are `YYYY-MM-DD` where they parsed
+    pub dates: Vec<String>,
+    pub records: Box<dyn Iterator<Item = Result<Record>> + 'a>,
 }
 
-pub fn process_csv(input: &str, output: String, format: Outputformat) -> Result<()>{
-    let mut reader = Reader::from_path(input)?;
-    let mut ret = Vec::with_capacity(128);
-    let headers = reader.headers()?.clone();
-    for result in reader.records() {
-        let record = result?; 
-        let json_value =  headers
-        .iter()
-        .zip(record.iter())
-        .collect::<serde_json::Value>();
-        ret.push(json_value);
+pub fn process_csv(source: &CsvSource, output: String, pipeline: &CsvPipeline) -> Result<()>{
+    let jobs = pipeline.jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    let mut progress = pipeline.progress.then(|| Progress::new(&source.input));
+    let rejects = Rc::new(RefCell::new(Rejects::new(pipeline.on_error, &pipeline.reject_file, source.dialect.delimiter)?));
+    let stream = apply_filter(open_records(source, &pipeline.out.root, jobs, progress.as_ref())?, &pipeline.filter)?;
+    let stream = match &pipeline.mapping {
+        Some(mapping) => map_records(stream, Mapping::load(mapping)?)?,
+        None => stream,
+    };
+    // rows the mapping cannot cast are rejected like the ones that cannot be read
+    let stream = recover_records(stream, rejects.clone());
+    if pipeline.out.keyed {
+        if let Some(key) = pipeline.filter.group_by.iter().find(|key| !stream.headers.contains(key)) {
+            anyhow::bail!("--keyed needs the --group-by column {:?} in the output, the mapping renames or drops it", key);
+        }
     }
+    let stream = reshape_records(stream, &pipeline.out)?;
+    let tsv = format_extension(&output).as_deref() == Some("tsv");
+    let out = OutputOpts {
+        format: Some(pipeline.out.format(Some(&output))),
+        out_delimiter: pipeline.out.out_delimiter.or(Some(if tsv { b'\t' } else { source.dialect.delimiter })),
+        ..pipeline.out.clone()
+    };
 
-    let content = match format {
-        Outputformat::Json => serde_json::to_string_pretty(&ret)?,
-        Outputformat::Yaml => serde_yaml::to_string(&ret)?,
+    let writer = BufWriter::new(output_writer(&output, &out)?);
+    let mut writer = match out.keyed {
+        true => keyed_writer(&out, &pipeline.filter.group_by, writer)?,
+        false => record_writer(&out, writer),
     };
+    writer.set_dates(&stream.dates);
+    writer.write_header(&stream.headers)?;
+    for record in stream.records {
+        writer.write_record(&record?)?;
+        if let Some(progress) = progress.as_mut() {
+            progress.tick();
+        }
+    }
+    writer.finish()?;
+    if let Some(progress) = progress.as_mut() {
+        progress.finish();
+    }
+    let mut rejects = rejects.borrow_mut();
+    rejects.finish()
+}
+
+/// Open the records of `source`, whatever its format. `table` names the TOML array of tables.
+pub fn source_records<'a>(source: &CsvSource, table: &str) -> Result<RecordStream<'a>> {
+    open_records(source, table, 1, None)
+}
+
+/// Like `source_records`, with CSV parsed on `jobs` threads and the bytes read counted for `progress`.
+fn open_records<'a>(source: &CsvSource, table: &str, jobs: usize, progress: Option<&Progress>) -> Result<RecordStream<'a>> {
+    let input = read_input(&source.input)?;
+    let input = decompress(match progress {
+        Some(progress) => Box::new(progress.counting(input)),
+        None => input,
+    })?;
+    match source.format() {
+        Inputformat::Csv if jobs > 1 => parallel_csv_records(decode_input(input, source.dialect.encoding)?, &source.dialect, &source.types, jobs),
+        Inputformat::Csv => csv_records(decode_input(input, source.dialect.encoding)?, &source.dialect, &source.types),
+        Inputformat::Fixed => fixed_records(decode_input(input, source.dialect.encoding)?, &source.dialect, &source.types),
+        Inputformat::Xlsx => xlsx_records(input, &source.dialect, &source.types),
+        format => structured_records(decode_input(input, source.dialect.encoding)?, format, table),
+    }
+}
+
+/// Typed records of a CSV input, the first `sample_rows` rows are buffered to infer column types.
+pub fn csv_records<'a, R: Read + 'a>(reader: R, dialect: &CsvDialect, types: &CsvTypes) -> Result<RecordStream<'a>> {
+    // field counts are checked by `string_record`, so a bad row can be rejected alone
+    let mut reader = dialect.reader_builder().flexible(true).from_reader(reader);
+    let headers = dialect.headers(&mut reader)?;
+    let width = dialect.width(&mut reader)?;
+    typed_records(headers, reader.into_byte_records(), width, types)
+}
+
+/// Type the raw records of a text format, `line` of their position is used in errors.
+/// Records without `width` fields, when given, are rejected.
+pub fn typed_records<'a>(
+    mut headers: Vec<String>,
+    records: impl Iterator<Item = csv::Result<ByteRecord>> + 'a,
+    width: Option<usize>,
+    types: &CsvTypes,
+) -> Result<RecordStream<'a>> {
+    let mut records = records.map(move |result| string_record(result?, width));
+    let (sample, columns) = infer_columns(&mut headers, &mut records, types)?;
+
+    let stream_headers = headers.clone();
+    let dates = columns.dates();
+    let records = sample.into_iter().chain(records).map(move |result| type_record(&mut headers, &columns, result?));
+    Ok(RecordStream {
+        headers: stream_headers,
+        dates,
+        records: Box::new(records),
+    })
+}
+
+/// Buffer the first `sample_rows` records and infer column types from the valid ones.
+/// `headers` grow to the widest of them, so writers with a fixed set of columns keep
+/// the fields `--flexible` rows have beyond the header.
+pub(super) fn infer_columns(
+    headers: &mut Vec<String>,
+    records: &mut impl Iterator<Item = Result<StringRecord>>,
+    types: &CsvTypes,
+) -> Result<(Vec<Result<StringRecord>>, TypedColumns)> {
+    let sample: Vec<_> = records.take(types.sample_rows).collect();
+    let valid: Vec<StringRecord> = sample.iter().filter_map(|r| r.as_ref().ok().cloned()).collect();
+    extend_headers(headers, valid.iter().map(StringRecord::len).max().unwrap_or_default());
+    let columns = TypedColumns::infer(headers, &valid, types)?;
+    Ok((sample, columns))
+}
+
+/// The fields of `record` as text, rejected when it isn't UTF-8 or doesn't have `width` fields.
+pub(super) fn string_record(record: ByteRecord, width: Option<usize>) -> Result<StringRecord> {
+    if let Some(width) = width.filter(|width| *width != record.len()) {
+        let reason = anyhow::anyhow!("found {} fields instead of {}", record.len(), width);
+        return Err(RejectedRecord::new(&record, reason).into());
+    }
+    StringRecord::from_byte_record(record).map_err(|e| {
+        let reason = anyhow::anyhow!("{}", e.utf8_error());
+        RejectedRecord::new(&e.into_byte_record(), reason).into()
+    })
+}
+
+/// Type `record`, naming any fields beyond `headers`, which `--flexible` allows.
+pub(super) fn type_record(headers: &mut Vec<String>, columns: &TypedColumns, record: StringRecord) -> Result<Record> {
+    extend_headers(headers, record.len());
+    typed_record(headers, columns, &record).map_err(|e| RejectedRecord::new(record.as_byte_record(), e).into())
+}
+
+pub fn typed_record(headers: &[String], columns: &TypedColumns, record: &StringRecord) -> Result<Record> {
+    let mut map = Map::with_capacity(record.len());
+    for (i, (name, field)) in headers.iter().zip(record.iter()).enumerate() {
+        map.insert(name.clone(), columns.value(i, field)?);
+    }
+    Ok(map)
+}
+
+impl CsvDialect {
+    pub fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .escape(self.escape)
+            .double_quote(self.escape.is_none())
+            .comment(self.comment)
+            .trim(self.trim.into())
+            .flexible(self.flexible)
+            .has_headers(self.header);
+        builder
+    }
+
+    /// Column names for `reader`: the header row (or `col_N` when there is none),
+    /// with any names given through `--columns` taking precedence.
+    pub fn headers<R: Read>(&self, reader: &mut Reader<R>) -> Result<Vec<String>> {
+        let first: Vec<String> = reader.headers()?.iter().map(String::from).collect();
+        Ok(self.column_names(first))
+    }
+
+    /// Fields every record must have: as many as the first row, unless `--flexible`.
+    pub fn width<R: Read>(&self, reader: &mut Reader<R>) -> Result<Option<usize>> {
+        Ok(match self.flexible {
+            true => None,
+            false => Some(reader.byte_headers()?.len()),
+        })
+    }
+
+    /// Column names given the `first` row, see `headers`.
+    pub fn column_names(&self, first: Vec<String>) -> Vec<String> {
+        let mut headers: Vec<String> = if self.header {
+            first
+        } else {
+            (1..=first.len()).map(column_name).collect()
+        };
+        for (i, name) in self.columns.iter().enumerate() {
+            match headers.get_mut(i) {
+                Some(header) => *header = name.clone(),
+                None => headers.push(name.clone()),
+            }
+        }
+        headers
+    }
+}
+
+/// Make room for rows wider than the header, which `--flexible` allows.
+pub fn extend_headers(headers: &mut Vec<String>, len: usize) {
+    while headers.len() < len {
+        headers.push(column_name(headers.len() + 1));
+    }
+}
+
+fn column_name(n: usize) -> String {
+    format!("col_{}", n)
+}
+
+impl From<CsvTrim> for Trim {
+    fn from(trim: CsvTrim) -> Self {
+        match trim {
+            CsvTrim::None => Trim::None,
+            CsvTrim::Headers => Trim::Headers,
+            CsvTrim::Fields => Trim::Fields,
+            CsvTrim::All => Trim::All,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cli::{CsvFilter, OnError}, utils::TempDir};
 
-    fs::write(output, content)?;
-    Ok(())
-} 
\ No newline at end of file
+    fn read_all(dialect: &CsvDialect, data: &str) -> Result<(Vec<String>, Vec<Vec<String>>)> {
+        let mut reader = dialect.reader_builder().from_reader(data.as_bytes());
+        let mut headers = dialect.headers(&mut reader)?;
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            extend_headers(&mut headers, record.len());
+            rows.push(record.iter().map(String::from).collect());
+        }
+        Ok((headers, rows))
+    }
+
+    #[test]
+    fn test_dialect_tsv_with_comments() -> Result<()> {
+        let dialect = CsvDialect {
+            delimiter: b'\t',
+            comment: Some(b'#'),
+            trim: CsvTrim::All,
+            ..Default::default()
+        };
+        let (headers, rows) = read_all(&dialect, "# exported\nName\t Kit \nBuffon\t 77\n")?;
+        assert_eq!(headers, vec!["Name", "Kit"]);
+        assert_eq!(rows, vec![vec!["Buffon", "77"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dialect_escape_and_quote() -> Result<()> {
+        let dialect = CsvDialect {
+            delimiter: b';',
+            quote: b'\'',
+            escape: Some(b'\\'),
+            ..Default::default()
+        };
+        let (_, rows) = read_all(&dialect, "a;b\n'x;\\'y';z\n")?;
+        assert_eq!(rows, vec![vec!["x;'y", "z"]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dialect_headerless() -> Result<()> {
+        let dialect = CsvDialect {
+            header: false,
+            flexible: true,
+            ..Default::default()
+        };
+        let (headers, rows) = read_all(&dialect, "1,2\n3,4,5\n")?;
+        assert_eq!(headers, vec!["col_1", "col_2", "col_3"]);
+        assert_eq!(rows.len(), 2);
+
+        let dialect = CsvDialect {
+            header: false,
+            columns: vec!["x".into(), "y".into()],
+            ..Default::default()
+        };
+        let (headers, rows) = read_all(&dialect, "1,2\n3,4\n")?;
+        assert_eq!(headers, vec!["x", "y"]);
+        assert_eq!(rows.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_flexible_to_csv() -> Result<()> {
+        let dialect = CsvDialect { header: false, flexible: true, ..Default::default() };
+        let stream = csv_records(&b"1,2\n3,4,5\n"[..], &dialect, &CsvTypes::default())?;
+        let mut buf = Vec::new();
+        let mut writer = record_writer(&OutputOpts { format: Some(crate::cli::Outputformat::Csv), ..Default::default() }, &mut buf);
+        writer.write_header(&stream.headers)?;
+        for record in stream.records {
+            writer.write_record(&record?)?;
+        }
+        writer.finish()?;
+        drop(writer);
+        assert_eq!(String::from_utf8(buf)?, "col_1,col_2,col_3\n1,2,\n3,4,5\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_mapped_rejects() -> Result<()> {
+        let dir = TempDir::new("convert")?;
+        let path = |name: &str| dir.path(name);
+        std::fs::write(path("in.csv"), "Name,Position,Kit Number\nBuffon,Goalkeeper,77\nPerin,Goalkeeper,n/a\n")?;
+        std::fs::write(path("map.toml"), "[columns.\"Kit Number\"]\ntype = \"int\"\n[columns.Position]\nname = \"role\"\n")?;
+        let source = CsvSource { input: path("in.csv"), from: None, dialect: Default::default(), types: CsvTypes { infer: false, ..Default::default() } };
+        std::fs::write(path("rename.toml"), "[columns.Position]\nname = \"role\"\n")?;
+        let pipeline = |on_error, mapping: &str, out: OutputOpts, filter: CsvFilter| CsvPipeline {
+            output: "-".into(),
+            out,
+            filter,
+            mapping: Some(path(mapping)),
+            jobs: Some(1),
+            progress: false,
+            on_error,
+            reject_file: path("rejects.csv"),
+        };
+        process_csv(&source, path("out.csv"), &pipeline(OnError::Skip, "map.toml", OutputOpts::default(), Default::default()))?;
+        let skipped = std::fs::read_to_string(path("out.csv"))?;
+        let failed = process_csv(&source, path("out.csv"), &pipeline(OnError::Fail, "map.toml", OutputOpts::default(), Default::default()));
+        let keyed = OutputOpts { keyed: true, ..Default::default() };
+        let filter = CsvFilter { group_by: vec!["Position".into()], ..Default::default() };
+        let renamed = process_csv(&source, path("out.json"), &pipeline(OnError::Fail, "rename.toml", keyed, filter));
+
+        assert_eq!(skipped, "Name,role,Kit Number\nBuffon,Goalkeeper,77\n");
+        assert_eq!(failed.map_err(|e| e.to_string()).unwrap_err(), "Invalid record");
+        assert!(renamed.is_err_and(|e| e.to_string().contains("\"Position\"")));
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD as URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::ChaCha20Poly1305;
+use serde_json::Value;
+
+use crate::cli::{CsvSource, CsvTypes, OutputOpts};
+use super::{
+    csv_convert::{source_records, RecordStream},
+    csv_nested::reshape_records,
+    csv_writer::write_csv_stream,
+    text::{load_cipher, open, seal, NONCE_SIZE},
+};
+
+/// Encrypt the cells of `columns`. The other cells are copied as text, so the rest of the
+/// file stays as it was.
+pub fn process_csv_encrypt(source: &CsvSource, columns: &[String], key: &str, output: &str, out: &OutputOpts) -> Result<()> {
+    let cipher = load_cipher(key).with_context(|| format!("Cannot load key {}", key))?;
+    let stream = source_records(&text_source(source), &out.root)?;
+    let stream = map_cells(stream, columns, move |value| encrypt_cell(&cipher, value))?;
+    write_csv_stream(reshape_records(stream, out)?, output, out)
+}
+
+pub fn process_csv_decrypt(source: &CsvSource, columns: &[String], key: &str, output: &str, out: &OutputOpts) -> Result<()> {
+    let cipher = load_cipher(key).with_context(|| format!("Cannot load key {}", key))?;
+    let stream = source_records(&text_source(source), &out.root)?;
+    let stream = map_cells(stream, columns, move |value| decrypt_cell(&cipher, value))?;
+    write_csv_stream(reshape_records(stream, out)?, output, out)
+}
+
+fn text_source(source: &CsvSource) -> CsvSource {
+    CsvSource {
+        types: CsvTypes { infer: false, overrides: Vec::new(), ..source.types.clone() },
+        ..source.clone()
+    }
+}
+
+/// Replace every non-empty cell of `columns` with `f` of it.
+fn map_cells<'a>(
+    stream: RecordStream<'a>,
+    columns: &[String],
+    f: impl Fn(&Value) -> Result<Value> + 'a,
+) -> Result<RecordStream<'a>> {
+    if let Some(column) = columns.iter().find(|c| !stream.headers.contains(c)) {
+        anyhow::bail!("Unknown column {:?}", column);
+    }
+    let columns = columns.to_vec();
+    let records = stream.records.enumerate().map(move |(i, record)| {
+        let mut record = record?;
+        for column in &columns {
+            match record.get_mut(column) {
+                Some(value) if !value.is_null() && value.as_str() != Some("") => {
+                    *value = f(value).with_context(|| format!("Row {}, column {:?}", i + 1, column))?;
+                }
+                _ => {}
+            }
+        }
+        Ok(record)
+    });
+    Ok(RecordStream { headers: stream.headers, dates: stream.dates, records: Box::new(records) })
+}
+
+/// The value is encrypted as JSON so decryption gives back numbers as numbers. The
+/// token is the base64url nonce followed by the ciphertext.
+fn encrypt_cell(cipher: &ChaCha20Poly1305, value: &Value) -> Result<Value> {
+    let (nonce, ciphertext) = seal(cipher, serde_json::to_string(value)?.as_bytes())?;
+    let mut token = nonce.to_vec();
+    token.extend(ciphertext);
+    Ok(Value::String(URL_SAFE_NO_PAD.encode(token)))
+}
+
+fn decrypt_cell(cipher: &ChaCha20Poly1305, value: &Value) -> Result<Value> {
+    let token = value
+        .as_str()
+        .and_then(|token| URL_SAFE_NO_PAD.decode(token).ok())
+        .filter(|token| token.len() > NONCE_SIZE)
+        .ok_or_else(|| anyhow::anyhow!("{} is not an encrypted cell", value))?;
+    let (nonce, ciphertext) = token.split_at(NONCE_SIZE);
+    let plaintext = open(cipher, nonce, ciphertext).context("Cannot decrypt, wrong key or tampered cell")?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use chacha20poly1305::KeyInit;
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn test_encrypt_decrypt_cells() -> Result<()> {
+        let cipher = ChaCha20Poly1305::new(&[1; 32].into());
+        for value in [json!("Italy"), json!(77), json!("1978-01-28")] {
+            let token = encrypt_cell(&cipher, &value)?;
+            assert_ne!(token, encrypt_cell(&cipher, &value)?, "every cell gets its own nonce");
+            assert_eq!(decrypt_cell(&cipher, &token)?, value);
+        }
+
+        let token = encrypt_cell(&cipher, &json!("Italy"))?;
+        let other = ChaCha20Poly1305::new(&[2; 32].into());
+        assert!(decrypt_cell(&other, &token).is_err());
+        assert!(decrypt_cell(&cipher, &json!("Italy")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_round_trip() -> Result<()> {
+        let dir = TempDir::new("crypt")?;
+        let path = |name: &str| dir.path(name);
+        let original = "Name,DOB,Height,Captain,Nationality\nBuffon,1978/01/28,1.90,TRUE,Italy\nDybala,,01.77,false,Argentina\n";
+        fs::write(path("in.csv"), original)?;
+        fs::write(path("key"), [7u8; 32])?;
+        let source = |input: String| CsvSource { input, from: None, dialect: Default::default(), types: Default::default() };
+        let columns = ["DOB".to_string(), "Nationality".to_string()];
+        let out = OutputOpts::default();
+
+        process_csv_encrypt(&source(path("in.csv")), &columns, &path("key"), &path("enc.txt"), &out)?;
+        process_csv_decrypt(&source(path("enc.txt")), &columns, &path("key"), &path("dec.txt"), &out)?;
+        let (encrypted, decrypted) = (fs::read_to_string(path("enc.txt"))?, fs::read_to_string(path("dec.txt"))?);
+
+        // Name, Height and Captain, as written
+        let untouched = |text: &str| -> Vec<Vec<String>> {
+            text.lines()
+                .map(|line| line.split(',').enumerate().filter(|(i, _)| [0, 2, 3].contains(i)).map(|(_, f)| f.to_string()).collect())
+                .collect()
+        };
+        assert_eq!(untouched(&encrypted), untouched(original));
+        assert!(!encrypted.contains("Italy"));
+        assert_eq!(decrypted, original);
+        Ok(())
+    }
+}
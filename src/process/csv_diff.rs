@@ -0,0 +1,263 @@
+use std::{collections::{HashMap, HashSet}, io::{IsTerminal, Write}};
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+
+use crate::{cli::{CsvSource, DiffFormat}, write_output};
+use super::{csv_convert::{source_records, Record}, csv_writer::cell_text};
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// Rows matched by key between two versions of a file.
+#[derive(Debug, Default)]
+pub struct CsvDiff {
+    pub key: Vec<String>,
+    pub headers: Vec<String>,
+    pub added_columns: Vec<String>,
+    pub removed_columns: Vec<String>,
+    pub added: Vec<Record>,
+    pub removed: Vec<Record>,
+    pub changed: Vec<Change>,
+}
+
+#[derive(Debug)]
+pub struct Change {
+    pub key: Vec<Value>,
+    pub record: Record,
+    /// column, before, after
+    pub fields: Vec<(String, Value, Value)>,
+}
+
+pub fn process_csv_diff(old: &CsvSource, new: &CsvSource, key: &[String], format: DiffFormat, output: &str) -> Result<()> {
+    let diff = diff_records(old, new, key)?;
+    let color = output == "-" && std::io::stdout().is_terminal();
+    let mut writer = write_output(output)?;
+    match format {
+        DiffFormat::Terminal => writer.write_all(diff.render(color).as_bytes())?,
+        DiffFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &diff.to_json())?;
+            writeln!(writer)?;
+        }
+        DiffFormat::Csv => diff.write_patch(&mut writer)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Match rows of `old` and `new` on the `key` columns. Fields are compared as text,
+/// so a column inferred differently in the two files doesn't count as a change.
+pub fn diff_records(old: &CsvSource, new: &CsvSource, key: &[String]) -> Result<CsvDiff> {
+    let old = source_records(old, "rows")?;
+    let new = source_records(new, "rows")?;
+    for column in key {
+        if !old.headers.contains(column) || !new.headers.contains(column) {
+            anyhow::bail!("Key column {:?} must be in both files", column);
+        }
+    }
+    let key_of = |record: &Record| -> Vec<Value> { key.iter().map(|k| record.get(k).cloned().unwrap_or(Value::Null)).collect() };
+    let text_key = |key: &[Value]| -> String { key.iter().map(|v| cell_text(v).into_owned()).collect::<Vec<_>>().join("\u{1f}") };
+
+    let mut diff = CsvDiff {
+        key: key.to_vec(),
+        headers: new.headers.clone(),
+        added_columns: new.headers.iter().filter(|h| !old.headers.contains(h)).cloned().collect(),
+        removed_columns: old.headers.iter().filter(|h| !new.headers.contains(h)).cloned().collect(),
+        ..Default::default()
+    };
+    let common: Vec<&String> = new.headers.iter().filter(|h| old.headers.contains(h)).collect();
+
+    let mut index = HashMap::new();
+    let mut old_records: Vec<Option<Record>> = Vec::new();
+    for record in old.records {
+        let record = record?;
+        let key = text_key(&key_of(&record));
+        if index.insert(key.clone(), old_records.len()).is_some() {
+            anyhow::bail!("Duplicate key {:?} in the old file", key);
+        }
+        old_records.push(Some(record));
+    }
+
+    let mut seen = HashSet::new();
+    for record in new.records {
+        let record = record?;
+        let values = key_of(&record);
+        let key = text_key(&values);
+        if !seen.insert(key.clone()) {
+            anyhow::bail!("Duplicate key {:?} in the new file", key);
+        }
+        let Some(before) = index.get(&key).and_then(|i| old_records[*i].take()) else {
+            diff.added.push(record);
+            continue;
+        };
+        let fields: Vec<(String, Value, Value)> = common
+            .iter()
+            .filter_map(|column| {
+                let a = before.get(*column).cloned().unwrap_or(Value::Null);
+                let b = record.get(*column).cloned().unwrap_or(Value::Null);
+                (cell_text(&a) != cell_text(&b)).then(|| (column.to_string(), a, b))
+            })
+            .collect();
+        if !fields.is_empty() {
+            diff.changed.push(Change { key: values, record, fields });
+        }
+    }
+    diff.removed = old_records.into_iter().flatten().collect();
+    Ok(diff)
+}
+
+impl CsvDiff {
+    fn key_text(&self, values: &[Value]) -> String {
+        self.key
+            .iter()
+            .zip(values)
+            .map(|(k, v)| format!("{}={}", k, cell_text(v)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn record_key(&self, record: &Record) -> Vec<Value> {
+        self.key.iter().map(|k| record.get(k).cloned().unwrap_or(Value::Null)).collect()
+    }
+
+    /// `+` added, `-` removed and `~` changed rows, with before → after per changed field.
+    pub fn render(&self, color: bool) -> String {
+        let paint = |code: &str, text: String| if color { format!("{}{}{}", code, text, RESET) } else { text };
+        let mut ret = String::new();
+        for column in &self.added_columns {
+            ret.push_str(&paint(GREEN, format!("+ column {}\n", column)));
+        }
+        for column in &self.removed_columns {
+            ret.push_str(&paint(RED, format!("- column {}\n", column)));
+        }
+        for record in &self.added {
+            ret.push_str(&paint(GREEN, format!("+ {}\n", self.key_text(&self.record_key(record)))));
+        }
+        for record in &self.removed {
+            ret.push_str(&paint(RED, format!("- {}\n", self.key_text(&self.record_key(record)))));
+        }
+        for change in &self.changed {
+            ret.push_str(&paint(YELLOW, format!("~ {}\n", self.key_text(&change.key))));
+            for (column, before, after) in &change.fields {
+                ret.push_str(&format!(
+                    "    {}: {} → {}\n",
+                    column,
+                    paint(RED, cell_text(before).into_owned()),
+                    paint(GREEN, cell_text(after).into_owned())
+                ));
+            }
+        }
+        ret.push_str(&format!(
+            "{} added, {} removed, {} changed\n",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        ));
+        ret
+    }
+
+    pub fn to_json(&self) -> Value {
+        let changed: Vec<Value> = self
+            .changed
+            .iter()
+            .map(|change| {
+                let key: Map<String, Value> = self.key.iter().cloned().zip(change.key.iter().cloned()).collect();
+                let fields: Map<String, Value> = change
+                    .fields
+                    .iter()
+                    .map(|(column, before, after)| (column.clone(), json!({ "before": before, "after": after })))
+                    .collect();
+                json!({ "key": key, "fields": fields })
+            })
+            .collect();
+        json!({
+            "columns": { "added": self.added_columns, "removed": self.removed_columns },
+            "added": self.added,
+            "removed": self.removed,
+            "changed": changed,
+        })
+    }
+
+    /// A CSV of the new columns plus an `_op` column: `add` and `update` rows carry the
+    /// new values, `delete` rows the old ones, enough to upsert and delete by key.
+    pub fn write_patch<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(std::iter::once("_op").chain(self.headers.iter().map(String::as_str)))?;
+        let rows = self
+            .added
+            .iter()
+            .map(|r| ("add", r))
+            .chain(self.changed.iter().map(|c| ("update", &c.record)))
+            .chain(self.removed.iter().map(|r| ("delete", r)));
+        for (op, record) in rows {
+            let fields = self.headers.iter().map(|h| record.get(h).map(cell_text).unwrap_or_default());
+            writer.write_record(std::iter::once(op.into()).chain(fields).map(|f| f.into_owned()))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    fn source(path: String) -> CsvSource {
+        CsvSource {
+            input: path,
+            from: None,
+            dialect: Default::default(),
+            types: Default::default(),
+        }
+    }
+
+    fn diff(old: &str, new: &str) -> Result<CsvDiff> {
+        let dir = TempDir::new("diff")?;
+        std::fs::write(dir.path("old.csv"), old)?;
+        std::fs::write(dir.path("new.csv"), new)?;
+        diff_records(&source(dir.path("old.csv")), &source(dir.path("new.csv")), &["Name".to_string()])
+    }
+
+    #[test]
+    fn test_diff() -> Result<()> {
+        let diff = diff(
+            "Name,Position,Kit Number\nBuffon,Goalkeeper,77\nPerin,Goalkeeper,37\nKhedira,Central Midfield,6\n",
+            "Name,Position,Kit Number\nBuffon,Goalkeeper,1\nKhedira,Central Midfield,6\nRamsey,Central Midfield,8\n",
+        )?;
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.removed[0]["Name"], json!("Perin"));
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].fields, vec![("Kit Number".to_string(), json!(77), json!(1))]);
+
+        assert_eq!(
+            diff.render(false),
+            "+ Name=Ramsey\n- Name=Perin\n~ Name=Buffon\n    Kit Number: 77 → 1\n1 added, 1 removed, 1 changed\n"
+        );
+        assert_eq!(diff.to_json()["changed"][0], json!({"key": {"Name": "Buffon"}, "fields": {"Kit Number": {"before": 77, "after": 1}}}));
+
+        let mut patch = Vec::new();
+        diff.write_patch(&mut patch)?;
+        assert_eq!(
+            String::from_utf8(patch)?,
+            "_op,Name,Position,Kit Number\n\
+             add,Ramsey,Central Midfield,8\n\
+             update,Buffon,Goalkeeper,1\n\
+             delete,Perin,Goalkeeper,37\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_columns_and_errors() -> Result<()> {
+        let columns = diff("Name,Club\nBuffon,PSG\n", "Name,Kit\nBuffon,77\n")?;
+        assert_eq!(columns.added_columns, vec!["Kit"]);
+        assert_eq!(columns.removed_columns, vec!["Club"]);
+        assert!(columns.changed.is_empty());
+
+        assert!(diff("Name\nBuffon\nBuffon\n", "Name\nBuffon\n").is_err());
+        assert!(diff("Player\nBuffon\n", "Name\nBuffon\n").is_err());
+        Ok(())
+    }
+}
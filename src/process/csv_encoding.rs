@@ -0,0 +1,160 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use anyhow::Result;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+
+use super::csv_writer::OutputWrite;
+
+/// Bytes looked at to guess the encoding of an input without a BOM.
+const SAMPLE_SIZE: usize = 64 * 1024;
+
+/// Transcode `reader` to UTF-8. Without an explicit `encoding` it is guessed from the
+/// BOM, or from the first bytes: UTF-16 if half of them are NUL, UTF-8 if they are
+/// valid, Windows-1252 otherwise. A BOM is stripped either way.
+pub fn decode_input(reader: Box<dyn Read>, encoding: Option<&'static Encoding>) -> Result<Box<dyn Read>> {
+    let mut reader = BufReader::with_capacity(SAMPLE_SIZE, reader);
+    let sample = reader.fill_buf()?;
+    let bom = Encoding::for_bom(sample).is_some();
+    let encoding = encoding.unwrap_or_else(|| detect_encoding(sample));
+    if encoding == UTF_8 && !bom {
+        return Ok(Box::new(reader));
+    }
+    let reader = DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .bom_override(true)
+        .build(reader);
+    Ok(Box::new(reader))
+}
+
+pub fn detect_encoding(sample: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(sample) {
+        return encoding;
+    }
+    // mostly ASCII text in UTF-16 has a NUL for every other byte
+    let nul_at = |parity: usize| sample.iter().skip(parity).step_by(2).filter(|b| **b == 0).count();
+    let half = sample.len() / 4;
+    if sample.len() >= 4 && nul_at(1) > half && nul_at(0) == 0 {
+        return UTF_16LE;
+    }
+    if sample.len() >= 4 && nul_at(0) > half && nul_at(1) == 0 {
+        return UTF_16BE;
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => UTF_8,
+        // the sample may end in the middle of a character
+        Err(e) if e.error_len().is_none() => UTF_8,
+        Err(_) => WINDOWS_1252,
+    }
+}
+
+/// Encodes the UTF-8 written to it into another encoding. UTF-16 output gets a BOM, as
+/// spreadsheet applications expect. Characters the encoding lacks are an error.
+pub struct EncodeWriter<W: Write> {
+    writer: W,
+    encoding: &'static Encoding,
+    pending: Vec<u8>,
+    started: bool,
+}
+
+impl<W: Write> EncodeWriter<W> {
+    pub fn new(writer: W, encoding: &'static Encoding) -> Self {
+        Self { writer, encoding, pending: Vec::new(), started: false }
+    }
+
+    fn encode(&self, text: &str) -> io::Result<Vec<u8>> {
+        let utf16 = |to_bytes: fn(u16) -> [u8; 2]| text.encode_utf16().flat_map(to_bytes).collect();
+        if self.encoding == UTF_16LE {
+            return Ok(utf16(u16::to_le_bytes));
+        }
+        if self.encoding == UTF_16BE {
+            return Ok(utf16(u16::to_be_bytes));
+        }
+        if let Some(c) = text.chars().find(|c| {
+            let mut buf = [0; 4];
+            self.encoding.encode(c.encode_utf8(&mut buf)).2
+        }) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Cannot encode {:?} as {}", c, self.encoding.name()),
+            ));
+        }
+        Ok(self.encoding.encode(text).0.into_owned())
+    }
+}
+
+impl<W: Write> Write for EncodeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.started {
+            self.started = true;
+            if self.encoding == UTF_16LE {
+                self.writer.write_all(&[0xff, 0xfe])?;
+            } else if self.encoding == UTF_16BE {
+                self.writer.write_all(&[0xfe, 0xff])?;
+            }
+        }
+        self.pending.extend_from_slice(buf);
+        // keep a character split across writes for the next one
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let text = std::str::from_utf8(&self.pending[..valid]).unwrap_or_default();
+        let bytes = self.encode(text)?;
+        self.writer.write_all(&bytes)?;
+        self.pending.drain(..valid);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: OutputWrite> OutputWrite for EncodeWriter<W> {
+    fn finish_output(&mut self) -> io::Result<()> {
+        self.writer.finish_output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &'static [u8], encoding: Option<&'static Encoding>) -> Result<String> {
+        let mut ret = String::new();
+        decode_input(Box::new(bytes), encoding)?.read_to_string(&mut ret)?;
+        Ok(ret)
+    }
+
+    #[test]
+    fn test_detect_encoding() -> Result<()> {
+        assert_eq!(decode(b"\xef\xbb\xbfName\nCaf\xc3\xa9\n", None)?, "Name\nCafé\n");
+        assert_eq!(decode(b"\xff\xfeN\0a\0m\0e\0\n\0", None)?, "Name\n");
+        assert_eq!(decode(b"N\0a\0m\0e\0\n\0", None)?, "Name\n");
+        assert_eq!(decode(b"\0N\0a\0m\0e\0\n", None)?, "Name\n");
+        assert_eq!(decode(b"Name\nCaf\xe9 \x80\n", None)?, "Name\nCafé €\n");
+        assert_eq!(decode(b"Name\nCaf\xc3\xa9\n", None)?, "Name\nCafé\n");
+        assert_eq!(decode(b"Caf\xe9", Some(encoding_rs::ISO_8859_2))?, "Café");
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_writer() -> Result<()> {
+        let mut buf = Vec::new();
+        let mut writer = EncodeWriter::new(&mut buf, WINDOWS_1252);
+        let text = "Café €\n".as_bytes();
+        // split inside é
+        writer.write_all(&text[..4])?;
+        writer.write_all(&text[4..])?;
+        assert_eq!(buf, b"Caf\xe9 \x80\n");
+
+        let mut buf = Vec::new();
+        EncodeWriter::new(&mut buf, UTF_16LE).write_all("Né".as_bytes())?;
+        assert_eq!(buf, b"\xff\xfeN\0\xe9\0");
+
+        let mut buf = Vec::new();
+        assert!(EncodeWriter::new(&mut buf, WINDOWS_1252).write_all("尤文".as_bytes()).is_err());
+        Ok(())
+    }
+}
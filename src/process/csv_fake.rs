@@ -0,0 +1,243 @@
+use std::fs;
+use anyhow::{Context, Result};
+use chrono::{format::{Item, StrftimeItems}, Datelike, NaiveDate};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::cli::{OutputOpts, Outputformat};
+use super::{
+    csv_convert::{Record, RecordStream},
+    csv_infer::parse_date,
+    csv_writer::write_stream,
+};
+
+const FIRST_NAMES: &[&str] = &[
+    "Alessandro", "Andrea", "Marco", "Luca", "Matteo", "Federico", "Giorgio", "Leonardo", "Paulo", "Cristiano",
+    "Wojciech", "Mattia", "Gianluigi", "Carlo", "Daniele", "Giovanni", "Mario", "Juan", "Sami", "Blaise",
+    "Adrien", "Aaron", "Miralem", "Douglas", "Emre", "Merih", "Rodrigo", "Moise", "Gonzalo", "Dejan",
+    "Kingsley", "Weston", "Dusan", "Manuel", "Nicolò", "Arkadiusz", "Timothy", "Samuel", "Kenan", "Filip",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Rossi", "Bianchi", "Ferrari", "Esposito", "Romano", "Colombo", "Ricci", "Marino", "Greco", "Bruno",
+    "Szczesny", "Perin", "Buffon", "Pinsoglio", "Chiellini", "Bonucci", "Rugani", "Cuadrado", "Khedira", "Matuidi",
+    "Rabiot", "Ramsey", "Pjanic", "Costa", "Can", "Demiral", "Bentancur", "Kean", "Higuain", "Kulusevski",
+    "Coman", "McKennie", "Vlahovic", "Locatelli", "Fagioli", "Milik", "Weah", "Iling", "Yildiz", "Kostic",
+];
+
+/// Columns to generate, read from TOML (`[[columns]]`) or JSON (`{"columns": [...]}`).
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FakeSchema {
+    pub columns: Vec<FakeColumn>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FakeColumn {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: FakeKind,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FakeKind {
+    /// First and last name
+    Name,
+    FirstName,
+    LastName,
+    /// A day between `min` and `max` inclusive, written with a chrono `format`
+    Date {
+        min: String,
+        max: String,
+        #[serde(default = "iso_date")]
+        format: String,
+    },
+    /// One of `values`
+    Enum { values: Vec<String> },
+    /// Between `min` and `max` inclusive
+    Int { min: i64, max: i64 },
+    /// `start`, `start + step`, ...
+    Sequence {
+        #[serde(default = "one")]
+        start: i64,
+        #[serde(default = "one")]
+        step: i64,
+    },
+    /// A random (version 4) UUID
+    Uuid,
+    /// A string matching `pattern`
+    Regex { pattern: String },
+}
+
+/// A column ready to generate values, with its parsed settings.
+enum Generator {
+    Name,
+    FirstName,
+    LastName,
+    Date { min: i32, max: i32, format: String },
+    Enum(Vec<String>),
+    Int { min: i64, max: i64 },
+    Sequence { next: i64, step: i64 },
+    Uuid,
+    Regex(rand_regex::Regex),
+}
+
+/// Write `rows` records shaped by `schema`. The same `seed` gives the same records.
+pub fn process_csv_fake(schema: &str, rows: usize, seed: Option<u64>, output: &str, out: &OutputOpts) -> Result<()> {
+    let schema = FakeSchema::load(schema)?;
+    let rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let stream = fake_records(&schema, rows, rng)?;
+    // CSV unless asked otherwise, the point is fixtures for CSV importers
+    let format = out.format.or_else(|| Outputformat::from_path(output)).unwrap_or(Outputformat::Csv);
+    write_stream(stream, output, &OutputOpts { format: Some(format), ..out.clone() })
+}
+
+pub fn fake_records(schema: &FakeSchema, rows: usize, mut rng: StdRng) -> Result<RecordStream<'static>> {
+    let headers: Vec<String> = schema.columns.iter().map(|c| c.name.clone()).collect();
+    let mut generators = schema
+        .columns
+        .iter()
+        .map(|c| Generator::new(&c.kind).with_context(|| format!("Invalid column {:?}", c.name)))
+        .collect::<Result<Vec<_>>>()?;
+    let dates = schema.columns.iter().filter(|c| matches!(c.kind, FakeKind::Date { .. })).map(|c| c.name.clone()).collect();
+    let columns = headers.clone();
+    let records = (0..rows).map(move |_| {
+        let record: Record = columns
+            .iter()
+            .zip(generators.iter_mut())
+            .map(|(name, generator)| (name.clone(), generator.generate(&mut rng)))
+            .collect();
+        Ok(record)
+    });
+    Ok(RecordStream { headers, dates, records: Box::new(records) })
+}
+
+impl FakeSchema {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| format!("Cannot read schema {}", path))?;
+        if path.to_ascii_lowercase().ends_with(".json") {
+            serde_json::from_str(&content).with_context(|| format!("Invalid schema {}", path))
+        } else {
+            toml::from_str(&content).with_context(|| format!("Invalid schema {}", path))
+        }
+    }
+}
+
+impl Generator {
+    fn new(kind: &FakeKind) -> Result<Self> {
+        Ok(match kind {
+            FakeKind::Name => Generator::Name,
+            FakeKind::FirstName => Generator::FirstName,
+            FakeKind::LastName => Generator::LastName,
+            FakeKind::Date { min, max, format } => {
+                let day = |date: &str| {
+                    parse_date(date)
+                        .map(|d| d.num_days_from_ce())
+                        .ok_or_else(|| anyhow::anyhow!("{:?} is not a date", date))
+                };
+                let (min, max) = (day(min)?, day(max)?);
+                if min > max {
+                    anyhow::bail!("min is after max");
+                }
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    anyhow::bail!("Invalid date format {:?}", format);
+                }
+                Generator::Date { min, max, format: format.clone() }
+            }
+            FakeKind::Enum { values } if values.is_empty() => anyhow::bail!("values is empty"),
+            FakeKind::Enum { values } => Generator::Enum(values.clone()),
+            FakeKind::Int { min, max } if min > max => anyhow::bail!("min is greater than max"),
+            FakeKind::Int { min, max } => Generator::Int { min: *min, max: *max },
+            FakeKind::Sequence { start, step } => Generator::Sequence { next: *start, step: *step },
+            FakeKind::Uuid => Generator::Uuid,
+            FakeKind::Regex { pattern } => Generator::Regex(rand_regex::Regex::compile(pattern, 16)?),
+        })
+    }
+
+    fn generate(&mut self, rng: &mut StdRng) -> Value {
+        let pick = |names: &[&str], rng: &mut StdRng| names.choose(rng).copied().unwrap_or_default().to_string();
+        match self {
+            Generator::Name => Value::String(format!("{} {}", pick(FIRST_NAMES, rng), pick(LAST_NAMES, rng))),
+            Generator::FirstName => Value::String(pick(FIRST_NAMES, rng)),
+            Generator::LastName => Value::String(pick(LAST_NAMES, rng)),
+            Generator::Date { min, max, format } => {
+                let date = NaiveDate::from_num_days_from_ce_opt(rng.gen_range(*min..=*max)).unwrap_or_default();
+                Value::String(date.format(format).to_string())
+            }
+            Generator::Enum(values) => Value::String(values.choose(rng).cloned().unwrap_or_default()),
+            Generator::Int { min, max } => Value::from(rng.gen_range(*min..=*max)),
+            Generator::Sequence { next, step } => {
+                let value = *next;
+                *next += *step;
+                Value::from(value)
+            }
+            Generator::Uuid => {
+                let mut bytes: [u8; 16] = rng.gen();
+                bytes[6] = (bytes[6] & 0x0f) | 0x40;
+                bytes[8] = (bytes[8] & 0x3f) | 0x80;
+                let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                Value::String(format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..]))
+            }
+            Generator::Regex(regex) => Value::String(rng.sample::<String, _>(&*regex)),
+        }
+    }
+}
+
+fn iso_date() -> String {
+    "%Y-%m-%d".to_string()
+}
+
+fn one() -> i64 {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    fn records(seed: u64, rows: usize) -> Result<Vec<Record>> {
+        let schema = FakeSchema::load("assets/juventus.fake.toml")?;
+        fake_records(&schema, rows, StdRng::seed_from_u64(seed))?.records.collect()
+    }
+
+    #[test]
+    fn test_fake_juventus() -> Result<()> {
+        let rows = records(7, 200)?;
+        assert_eq!(rows.len(), 200);
+        assert_eq!(rows, records(7, 200)?);
+        assert_ne!(rows, records(8, 200)?);
+
+        let uuid = Regex::new("^[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$")?;
+        let contract = Regex::new("^JUV-[0-9]{4}-[A-Z]{2}$")?;
+        for row in &rows {
+            assert!(row["Name"].as_str().is_some_and(|name| name.contains(' ')));
+            let kit = row["Kit Number"].as_i64().unwrap();
+            assert!((1..=99).contains(&kit));
+            let dob = parse_date(row["DOB"].as_str().unwrap()).unwrap();
+            assert!((1978..=2003).contains(&dob.year()));
+            assert!(uuid.is_match(row["Player Id"].as_str().unwrap()));
+            assert!(contract.is_match(row["Contract"].as_str().unwrap()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fake_schema() -> Result<()> {
+        let schema: FakeSchema = toml::from_str("[[columns]]\nname = \"Id\"\nkind = \"sequence\"\nstart = 10\nstep = 5")?;
+        let rows: Vec<Record> = fake_records(&schema, 3, StdRng::seed_from_u64(1))?.records.collect::<Result<_>>()?;
+        let ids: Vec<&Value> = rows.iter().map(|r| &r["Id"]).collect();
+        assert_eq!(ids, [10, 15, 20]);
+
+        assert!(toml::from_str::<FakeSchema>("[[columns]]\nname = \"a\"\nkind = \"zip\"").is_err());
+        assert!(toml::from_str::<FakeSchema>("[[columns]]\nname = \"a\"\nkind = \"int\"\nmin = 1").is_err());
+        let reversed: FakeSchema = toml::from_str("[[columns]]\nname = \"a\"\nkind = \"int\"\nmin = 9\nmax = 1")?;
+        assert!(fake_records(&reversed, 1, StdRng::seed_from_u64(1)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,469 @@
+use std::{cmp::Ordering, str::FromStr};
+use anyhow::Result;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::cli::{CsvFilter, SortKey, SortMode};
+use super::{csv_convert::{Record, RecordStream}, csv_group::group_records, csv_reject::RejectedRecord, csv_writer::cell_text};
+
+/// A `--where` expression: comparisons of a column with a literal, combined with
+/// `and`, `or`, `not` and parentheses.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(String, CompareOp, Value),
+    Contains(String, String),
+    Matches(String, Regex),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Ident(String),
+    Op(String),
+    LParen,
+    RParen,
+}
+
+/// Apply `--where`, `--sort-by`, `--select` and `--exclude`, in that order.
+/// Sorting needs every record in memory, the rest stays streaming. Rejected rows
+/// go through untouched, for `--on-error` to deal with once records are mapped.
+pub fn apply_filter<'a>(stream: RecordStream<'a>, filter: &CsvFilter) -> Result<RecordStream<'a>> {
+    let known = |headers: &[String], column: &String| -> Result<()> {
+        match headers.contains(column) {
+            true => Ok(()),
+            false => Err(anyhow::anyhow!("Unknown column {:?}", column)),
+        }
+    };
+    if let Some(expr) = &filter.filter {
+        expr.columns().into_iter().try_for_each(|c| known(&stream.headers, c))?;
+    }
+    let agg_columns = filter.agg.iter().filter_map(|agg| agg.column.as_ref());
+    filter.group_by.iter().chain(agg_columns).try_for_each(|c| known(&stream.headers, c))?;
+
+    let expr = filter.filter.clone();
+    let records: Box<dyn Iterator<Item = Result<Record>> + 'a> = Box::new(stream.records.filter(move |record| {
+        match (record, &expr) {
+            (Ok(record), Some(expr)) => expr.eval(record),
+            _ => true,
+        }
+    }));
+    // sorting and projection see the grouped columns
+    let RecordStream { headers, dates, mut records } = match filter.group_by.is_empty() && filter.agg.is_empty() {
+        true => RecordStream { headers: stream.headers, dates: stream.dates, records },
+        false => group_records(RecordStream { headers: stream.headers, dates: stream.dates, records }, &filter.group_by, &filter.agg)?,
+    };
+    filter.select.iter().chain(&filter.exclude).try_for_each(|c| known(&headers, c))?;
+    filter.sort_by.iter().try_for_each(|key| known(&headers, &key.column))?;
+
+    let columns: Vec<String> = if filter.select.is_empty() {
+        headers.clone()
+    } else {
+        filter.select.clone()
+    };
+    let columns: Vec<String> = columns.into_iter().filter(|c| !filter.exclude.contains(c)).collect();
+    let project = columns != headers;
+
+    if !filter.sort_by.is_empty() {
+        let mut sorted = Vec::new();
+        let mut rejected = Vec::new();
+        for record in records {
+            match record {
+                Err(e) if e.is::<RejectedRecord>() => rejected.push(Err(e)),
+                record => sorted.push(record?),
+            }
+        }
+        sorted.sort_by(|a, b| compare_records(a, b, &filter.sort_by));
+        records = Box::new(rejected.into_iter().chain(sorted.into_iter().map(Ok)));
+    }
+    if project {
+        let columns = columns.clone();
+        records = Box::new(records.map(move |record| {
+            let mut record = record?;
+            Ok(columns
+                .iter()
+                .map(|c| (c.clone(), record.remove(c).unwrap_or(Value::Null)))
+                .collect())
+        }));
+    }
+    Ok(RecordStream { headers: columns, dates, records })
+}
+
+/// Nulls and empty strings sort last whichever the direction.
+fn compare_records(a: &Record, b: &Record, keys: &[SortKey]) -> Ordering {
+    let empty = |v: &Value| v.is_null() || v.as_str() == Some("");
+    keys.iter()
+        .map(|key| {
+            let a = a.get(&key.column).unwrap_or(&Value::Null);
+            let b = b.get(&key.column).unwrap_or(&Value::Null);
+            match (empty(a), empty(b)) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                _ if key.desc => compare_values(a, b, key.mode).reverse(),
+                _ => compare_values(a, b, key.mode),
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// In numeric mode, values that aren't numbers sort after numbers.
+pub fn compare_values(a: &Value, b: &Value, mode: SortMode) -> Ordering {
+    let numeric = match mode {
+        SortMode::Lexical => None,
+        SortMode::Auto => a.as_f64().zip(b.as_f64()),
+        SortMode::Numeric => match (as_number(a), as_number(b)) {
+            (Some(a), Some(b)) => Some((a, b)),
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            (None, None) => None,
+        },
+    };
+    match numeric {
+        Some((a, b)) => a.total_cmp(&b),
+        None => cell_text(a).cmp(&cell_text(b)),
+    }
+}
+
+pub fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, record: &Record) -> bool {
+        let get = |column: &str| record.get(column).unwrap_or(&Value::Null);
+        match self {
+            Expr::And(a, b) => a.eval(record) && b.eval(record),
+            Expr::Or(a, b) => a.eval(record) || b.eval(record),
+            Expr::Not(e) => !e.eval(record),
+            Expr::Compare(column, op, literal) => compare(get(column), *op, literal),
+            Expr::Contains(column, needle) => cell_text(get(column)).contains(needle.as_str()),
+            Expr::Matches(column, re) => re.is_match(&cell_text(get(column))),
+        }
+    }
+
+    /// Every column the expression refers to.
+    pub fn columns(&self) -> Vec<&String> {
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                let mut ret = a.columns();
+                ret.extend(b.columns());
+                ret
+            }
+            Expr::Not(e) => e.columns(),
+            Expr::Compare(column, _, _) | Expr::Contains(column, _) | Expr::Matches(column, _) => vec![column],
+        }
+    }
+}
+
+fn compare(value: &Value, op: CompareOp, literal: &Value) -> bool {
+    let ordering = match (value, literal) {
+        (_, Value::Null) | (Value::Null, _) => {
+            let eq = value.is_null() == literal.is_null();
+            return match op {
+                CompareOp::Eq => eq,
+                CompareOp::Ne => !eq,
+                _ => false,
+            };
+        }
+        (_, Value::Number(n)) => match as_number(value) {
+            Some(v) => v.total_cmp(&n.as_f64().unwrap_or(f64::NAN)),
+            None => return op == CompareOp::Ne,
+        },
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        _ => cell_text(value).as_ref().cmp(cell_text(literal).as_ref()),
+    };
+    match op {
+        CompareOp::Eq => ordering.is_eq(),
+        CompareOp::Ne => ordering.is_ne(),
+        CompareOp::Lt => ordering.is_lt(),
+        CompareOp::Le => ordering.is_le(),
+        CompareOp::Gt => ordering.is_gt(),
+        CompareOp::Ge => ordering.is_ge(),
+    }
+}
+
+impl FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(anyhow::anyhow!("Unexpected {:?} in expression", token)),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '\'' | '"' | '`' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        // a doubled quote stands for the quote itself
+                        Some(q) if q == c && chars.peek() == Some(&c) => {
+                            chars.next();
+                            text.push(c);
+                        }
+                        Some(q) if q == c => break,
+                        Some(q) => text.push(q),
+                        None => anyhow::bail!("Unterminated {} in expression", c),
+                    }
+                }
+                tokens.push(if c == '\'' { Token::Quoted(text) } else { Token::Ident(text) });
+            }
+            '=' | '!' | '<' | '>' | '~' => {
+                let mut op = String::from(c);
+                chars.next();
+                if let Some(&next) = chars.peek() {
+                    if matches!(next, '=' | '>') && !(c == '>' && next == '>') {
+                        op.push(next);
+                        chars.next();
+                    }
+                }
+                tokens.push(Token::Op(op));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "()'\"`=!<>~".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.unary()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.tokens.get(self.pos) == Some(&Token::LParen) {
+            self.pos += 1;
+            let expr = self.or()?;
+            return match self.next() {
+                Some(Token::RParen) => Ok(expr),
+                _ => Err(anyhow::anyhow!("Missing ) in expression")),
+            };
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let column = match self.next() {
+            Some(Token::Word(w)) | Some(Token::Ident(w)) => w,
+            other => anyhow::bail!("Expected a column name, found {:?}", other),
+        };
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            Some(Token::Word(w)) => w.to_ascii_lowercase(),
+            other => anyhow::bail!("Expected an operator after {:?}, found {:?}", column, other),
+        };
+        let literal = match self.next() {
+            Some(Token::Quoted(s)) | Some(Token::Ident(s)) => Value::String(s),
+            Some(Token::Word(w)) => literal(&w),
+            other => anyhow::bail!("Expected a value after {:?}, found {:?}", op, other),
+        };
+        let op = match op.as_str() {
+            "=" | "==" => CompareOp::Eq,
+            "!=" | "<>" => CompareOp::Ne,
+            "<" => CompareOp::Lt,
+            "<=" => CompareOp::Le,
+            ">" => CompareOp::Gt,
+            ">=" => CompareOp::Ge,
+            "contains" => return Ok(Expr::Contains(column, cell_text(&literal).into_owned())),
+            "~" | "matches" => return Ok(Expr::Matches(column, Regex::new(&cell_text(&literal))?)),
+            _ => anyhow::bail!("Unknown operator {:?}", op),
+        };
+        Ok(Expr::Compare(column, op, literal))
+    }
+}
+
+/// Unquoted values are numbers, booleans, `null` or otherwise plain strings.
+fn literal(word: &str) -> Value {
+    match word {
+        "null" => Value::Null,
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => serde_json::from_str::<serde_json::Number>(word)
+            .map(Value::Number)
+            .unwrap_or_else(|_| Value::String(word.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn records() -> Vec<Record> {
+        let rows = json!([
+            {"Name": "Gianluigi Buffon", "Position": "Goalkeeper", "Kit Number": 77},
+            {"Name": "Paulo Dybala", "Position": "Second Striker", "Kit Number": 10},
+            {"Name": "Cristiano Ronaldo", "Position": "Left Winger", "Kit Number": 7},
+            {"Name": "Mattia Perin", "Position": "Goalkeeper", "Kit Number": null},
+        ]);
+        rows.as_array().unwrap().iter().map(|r| r.as_object().unwrap().clone()).collect()
+    }
+
+    fn names(filter: &CsvFilter) -> Result<(Vec<String>, Vec<Record>)> {
+        let records = records();
+        let stream = RecordStream {
+            headers: records[0].keys().cloned().collect(),
+            dates: Vec::new(),
+            records: Box::new(records.into_iter().map(Ok)),
+        };
+        let stream = apply_filter(stream, filter)?;
+        Ok((stream.headers, stream.records.collect::<Result<_>>()?))
+    }
+
+    fn matching(expr: &str) -> Vec<String> {
+        let expr: Expr = expr.parse().unwrap();
+        records()
+            .iter()
+            .filter(|r| expr.eval(r))
+            .map(|r| r["Name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_where_expressions() {
+        assert_eq!(matching("Position = Goalkeeper"), vec!["Gianluigi Buffon", "Mattia Perin"]);
+        assert_eq!(matching(r#""Kit Number" < 20"#), vec!["Paulo Dybala", "Cristiano Ronaldo"]);
+        assert_eq!(
+            matching("Position = 'Goalkeeper' and not `Kit Number` = null"),
+            vec!["Gianluigi Buffon"]
+        );
+        assert_eq!(
+            matching("Name contains 'Ronaldo' or (Position ~ '^Sec' and \"Kit Number\" >= 10)"),
+            vec!["Paulo Dybala", "Cristiano Ronaldo"]
+        );
+        assert_eq!(matching("Name matches 'o$'"), vec!["Cristiano Ronaldo"]);
+        assert_eq!(matching("`Kit Number` != 7").len(), 3);
+        assert!("'Kit Number' != 7".parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn test_where_errors() {
+        assert!("Name".parse::<Expr>().is_err());
+        assert!("Name = ".parse::<Expr>().is_err());
+        assert!("Name like 'x'".parse::<Expr>().is_err());
+        assert!("(Name = x".parse::<Expr>().is_err());
+        assert!("Name = 'x".parse::<Expr>().is_err());
+        assert!("Name ~ '('".parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn test_select_exclude_sort() -> Result<()> {
+        let filter = CsvFilter {
+            select: vec!["Kit Number".into(), "Name".into(), "Position".into()],
+            exclude: vec!["Position".into()],
+            sort_by: vec!["Kit Number:desc".parse()?],
+            ..Default::default()
+        };
+        let (headers, records) = names(&filter)?;
+        assert_eq!(headers, vec!["Kit Number", "Name"]);
+        let kits: Vec<_> = records.iter().map(|r| r["Kit Number"].clone()).collect();
+        assert_eq!(kits, vec![json!(77), json!(10), json!(7), Value::Null]);
+        assert_eq!(records[0].keys().collect::<Vec<_>>(), vec!["Kit Number", "Name"]);
+
+        let filter = CsvFilter {
+            sort_by: vec!["Position".parse()?, "Name:desc:lex".parse()?],
+            ..Default::default()
+        };
+        let (_, records) = names(&filter)?;
+        assert_eq!(records[0]["Name"], json!("Mattia Perin"));
+        assert_eq!(records[1]["Name"], json!("Gianluigi Buffon"));
+
+        let filter = CsvFilter { select: vec!["Club".into()], ..Default::default() };
+        assert!(names(&filter).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_numeric_sort_of_strings() {
+        let (a, b) = (json!("10"), json!("9"));
+        assert_eq!(compare_values(&a, &b, SortMode::Numeric), Ordering::Greater);
+        assert_eq!(compare_values(&a, &b, SortMode::Lexical), Ordering::Less);
+        assert_eq!(compare_values(&json!("x"), &b, SortMode::Numeric), Ordering::Greater);
+    }
+}
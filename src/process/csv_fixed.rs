@@ -0,0 +1,134 @@
+use std::{fs, io::{BufRead, BufReader, Read}};
+use anyhow::{Context, Result};
+use csv::{Position, StringRecord};
+use serde::Deserialize;
+
+use crate::cli::{CsvDialect, CsvTypes};
+use super::csv_convert::{typed_records, RecordStream};
+
+/// Where the columns of a fixed-width file are, read from TOML (`[[columns]]`) or JSON.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixedLayout {
+    /// Lines skipped at the start, like a banner or a header
+    #[serde(default)]
+    pub skip: usize,
+    pub columns: Vec<FixedColumn>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixedColumn {
+    pub name: String,
+    /// Position of the first character, counting from 1
+    pub start: usize,
+    /// Number of characters
+    pub width: usize,
+    #[serde(default)]
+    pub trim: FixedTrim,
+}
+
+/// Which padding is removed from a field.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FixedTrim {
+    None,
+    Left,
+    Right,
+    #[default]
+    Both,
+}
+
+/// Records of a fixed-width input cut by the `--layout` columns, typed like CSV fields.
+/// Blank lines and lines starting with the `--comment` character are skipped.
+pub fn fixed_records<'a>(reader: Box<dyn Read>, dialect: &CsvDialect, types: &CsvTypes) -> Result<RecordStream<'a>> {
+    let path = dialect.layout.as_deref().ok_or_else(|| anyhow::anyhow!("Fixed-width input needs --layout"))?;
+    let layout = FixedLayout::load(path)?;
+    let headers = layout.columns.iter().map(|c| c.name.clone()).collect();
+    let comment = dialect.comment;
+    let records = BufReader::new(reader)
+        .lines()
+        .enumerate()
+        .skip(layout.skip)
+        .filter(move |(_, line)| match line {
+            Ok(line) => !line.trim().is_empty() && comment.is_none_or(|c| !line.starts_with(c as char)),
+            Err(_) => true,
+        })
+        .map(move |(i, line)| {
+            let mut record = layout.cut(&line?);
+            let mut position = Position::new();
+            position.set_line(i as u64 + 1);
+            record.set_position(Some(position));
+            Ok(record.into_byte_record())
+        });
+    typed_records(headers, records, None, types)
+}
+
+impl FixedLayout {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| format!("Cannot read layout {}", path))?;
+        let layout: FixedLayout = if path.to_ascii_lowercase().ends_with(".json") {
+            serde_json::from_str(&content).with_context(|| format!("Invalid layout {}", path))?
+        } else {
+            toml::from_str(&content).with_context(|| format!("Invalid layout {}", path))?
+        };
+        for column in &layout.columns {
+            if column.start == 0 || column.width == 0 {
+                anyhow::bail!("Column {:?} needs a start and a width of at least 1", column.name);
+            }
+        }
+        Ok(layout)
+    }
+
+    /// The fields of `line`, empty where the line is too short. Positions count characters.
+    fn cut(&self, line: &str) -> StringRecord {
+        let chars: Vec<(usize, char)> = line.char_indices().collect();
+        let offset = |n: usize| chars.get(n).map_or(line.len(), |(i, _)| *i);
+        self.columns
+            .iter()
+            .map(|column| {
+                let field = &line[offset(column.start - 1)..offset(column.start - 1 + column.width)];
+                match column.trim {
+                    FixedTrim::None => field,
+                    FixedTrim::Left => field.trim_start(),
+                    FixedTrim::Right => field.trim_end(),
+                    FixedTrim::Both => field.trim(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_cut() -> Result<()> {
+        let layout: FixedLayout = toml::from_str(
+            "[[columns]]\nname = \"Name\"\nstart = 1\nwidth = 8\n\n\
+             [[columns]]\nname = \"Code\"\nstart = 9\nwidth = 4\ntrim = \"none\"\n\n\
+             [[columns]]\nname = \"Kit\"\nstart = 13\nwidth = 3\ntrim = \"left\"",
+        )?;
+        assert_eq!(layout.cut("Buffon  IT   77"), StringRecord::from(vec!["Buffon", "IT  ", "77"]));
+        assert_eq!(layout.cut("Nicolò  IT  "), StringRecord::from(vec!["Nicolò", "IT  ", ""]));
+        assert!(toml::from_str::<FixedLayout>("[[columns]]\nname = \"a\"\nstart = 1\nwidth = 2\ntrim = \"all\"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixed_juventus() -> Result<()> {
+        let dialect = CsvDialect { layout: Some("assets/juventus.layout.toml".into()), ..Default::default() };
+        let reader = crate::read_input("assets/juventus.txt")?;
+        let stream = fixed_records(reader, &dialect, &Default::default())?;
+        assert_eq!(stream.headers, vec!["Name", "Position", "DOB", "Nationality", "Kit Number"]);
+        let records = stream.records.collect::<Result<Vec<_>>>()?;
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            serde_json::Value::Object(records[2].clone()),
+            json!({"Name": "Gianluigi Buffon", "Position": "Goalkeeper", "DOB": "1978-01-28", "Nationality": "Italy", "Kit Number": 77})
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::cli::{AggFunc, AggSpec};
+use super::{csv_convert::{Record, RecordStream}, csv_query::aggregate, csv_reject::RejectedRecord, csv_writer::cell_text};
+
+/// One record per distinct value of the `group_by` columns, in order of first appearance,
+/// holding the group columns then one column per aggregate (`count` when none is given).
+/// Without group columns all records make up a single group.
+pub fn group_records<'a>(stream: RecordStream<'a>, group_by: &[String], aggs: &[AggSpec]) -> Result<RecordStream<'a>> {
+    let count = [AggSpec { func: AggFunc::Count, column: None }];
+    let aggs = if aggs.is_empty() { &count[..] } else { aggs };
+
+    let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+    // group values, then the values of each aggregate
+    let mut groups: Vec<(Vec<Value>, Vec<Vec<Value>>)> = Vec::new();
+    // rejected rows are left out of the groups and passed on
+    let mut rejected = Vec::new();
+    for record in stream.records {
+        let record = match record {
+            Err(e) if e.is::<RejectedRecord>() => {
+                rejected.push(Err(e));
+                continue;
+            }
+            record => record?,
+        };
+        let key: Vec<Value> = group_by.iter().map(|c| record.get(c).cloned().unwrap_or(Value::Null)).collect();
+        let text_key = key.iter().map(|v| cell_text(v).into_owned()).collect();
+        let i = *index.entry(text_key).or_insert_with(|| {
+            groups.push((key, vec![Vec::new(); aggs.len()]));
+            groups.len() - 1
+        });
+        for (agg, values) in aggs.iter().zip(groups[i].1.iter_mut()) {
+            let value = match &agg.column {
+                Some(column) => record.get(column).cloned().unwrap_or(Value::Null),
+                None => Value::Bool(true),
+            };
+            if !value.is_null() && value.as_str() != Some("") {
+                values.push(value);
+            }
+        }
+    }
+    if groups.is_empty() && group_by.is_empty() {
+        groups.push((Vec::new(), vec![Vec::new(); aggs.len()]));
+    }
+
+    let mut headers = group_by.to_vec();
+    headers.extend(aggs.iter().map(|agg| agg.to_string()));
+    // group columns and the min/max of date columns are still dates
+    let dates = headers
+        .iter()
+        .zip(group_by.iter().map(Some).chain(aggs.iter().map(|agg| match agg.func {
+            AggFunc::Min | AggFunc::Max => agg.column.as_ref(),
+            _ => None,
+        })))
+        .filter(|(_, column)| column.is_some_and(|column| stream.dates.contains(column)))
+        .map(|(header, _)| header.clone())
+        .collect();
+    let records: Vec<Result<Record>> = rejected
+        .into_iter()
+        .chain(groups.into_iter().map(|(key, values)| {
+            let mut record: Record = group_by.iter().cloned().zip(key).collect();
+            for (agg, values) in aggs.iter().zip(values) {
+                record.insert(agg.to_string(), aggregate(agg.func, values));
+            }
+            Ok(record)
+        }))
+        .collect();
+    Ok(RecordStream { headers, dates, records: Box::new(records.into_iter()) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::CsvSource;
+    use crate::process::csv_convert::source_records;
+    use serde_json::json;
+
+    fn juventus() -> RecordStream<'static> {
+        let source = CsvSource {
+            input: "assets/juventus.csv".into(),
+            from: None,
+            dialect: Default::default(),
+            types: Default::default(),
+        };
+        source_records(&source, "rows").unwrap()
+    }
+
+    #[test]
+    fn test_group_by() -> Result<()> {
+        let aggs: Vec<AggSpec> = ["count", "min(DOB)", "max(Kit Number)", "avg(Kit Number)"]
+            .iter()
+            .map(|a| a.parse())
+            .collect::<Result<_>>()?;
+        let stream = group_records(juventus(), &["Position".to_string()], &aggs)?;
+        assert_eq!(stream.headers, vec!["Position", "count", "min(DOB)", "max(Kit Number)", "avg(Kit Number)"]);
+        let records = stream.records.collect::<Result<Vec<_>>>()?;
+        assert_eq!(records.len(), 10);
+        assert_eq!(
+            Value::Object(records[0].clone()),
+            json!({"Position": "Goalkeeper", "count": 4, "min(DOB)": "Jan 28, 1978 (41)", "max(Kit Number)": 77, "avg(Kit Number)": 36.5})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_all() -> Result<()> {
+        let stream = group_records(juventus(), &[], &["sum(Kit Number)".parse()?])?;
+        let records = stream.records.collect::<Result<Vec<_>>>()?;
+        assert_eq!(records, vec![json!({"sum(Kit Number)": 492}).as_object().unwrap().clone()]);
+        Ok(())
+    }
+}
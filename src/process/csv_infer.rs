@@ -0,0 +1,233 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use csv::StringRecord;
+use serde_json::{Number, Value};
+
+use crate::cli::{ColumnType, CsvTypes};
+
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%Y/%m/%d", "%b %d, %Y", "%d %b %Y"];
+
+#[derive(Debug, Clone)]
+struct TypedColumn {
+    name: String,
+    ty: ColumnType,
+    // set for `--type` overrides: fields that don't parse are an error instead of
+    // falling back to a string
+    strict: bool,
+}
+
+/// The type of every column, inferred from a sample of rows or given on the command line.
+#[derive(Debug, Clone)]
+pub struct TypedColumns {
+    columns: Vec<TypedColumn>,
+    infer: bool,
+}
+
+impl TypedColumns {
+    pub fn infer(headers: &[String], sample: &[StringRecord], opts: &CsvTypes) -> Result<Self> {
+        let mut columns: Vec<TypedColumn> = headers
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let ty = if opts.infer {
+                    sample
+                        .iter()
+                        .filter_map(|record| record.get(i).and_then(detect))
+                        .reduce(merge)
+                        .unwrap_or(ColumnType::String)
+                } else {
+                    ColumnType::String
+                };
+                TypedColumn { name: name.clone(), ty, strict: !opts.infer }
+            })
+            .collect();
+
+        for (name, ty) in &opts.overrides {
+            let column = columns
+                .iter_mut()
+                .find(|c| &c.name == name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown column {:?} in --type", name))?;
+            column.ty = *ty;
+            column.strict = true;
+        }
+        Ok(Self { columns, infer: opts.infer })
+    }
+
+    /// Names of the columns typed as dates.
+    pub fn dates(&self) -> Vec<String> {
+        self.columns.iter().filter(|c| c.ty == ColumnType::Date).map(|c| c.name.clone()).collect()
+    }
+
+    /// Convert the field of column `i`. Columns past the header (flexible rows)
+    /// are treated like an inferred string column.
+    pub fn value(&self, i: usize, field: &str) -> Result<Value> {
+        let Some(column) = self.columns.get(i) else {
+            return Ok(match field {
+                "" if self.infer => Value::Null,
+                _ => Value::String(field.to_string()),
+            });
+        };
+        match (column.ty, column.strict) {
+            (ColumnType::String, true) => Ok(Value::String(field.to_string())),
+            _ if field.is_empty() => Ok(Value::Null),
+            (ty, strict) => match parse_field(field, ty) {
+                Some(value) => Ok(value),
+                None if strict => Err(anyhow::anyhow!(
+                    "Cannot parse {:?} as {} in column {:?}",
+                    field,
+                    ty,
+                    column.name
+                )),
+                None => Ok(Value::String(field.to_string())),
+            },
+        }
+    }
+}
+
+/// Parse `field` as `ty`. Dates are normalized to ISO 8601 (`YYYY-MM-DD`) and may be
+/// followed by a space and more text, like `Apr 18, 1990 (29)`.
+pub fn parse_field(field: &str, ty: ColumnType) -> Option<Value> {
+    match ty {
+        ColumnType::String => Some(Value::String(field.to_string())),
+        ColumnType::Int => field.parse::<i64>().ok().map(Value::from),
+        ColumnType::Float => {
+            if !field.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b)) {
+                return None;
+            }
+            field.parse::<f64>().ok().and_then(Number::from_f64).map(Value::Number)
+        }
+        ColumnType::Bool => match field.to_ascii_lowercase().as_str() {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        ColumnType::Date => parse_leading_date(field).map(|d| Value::String(d.format("%Y-%m-%d").to_string())),
+    }
+}
+
+pub fn parse_date(field: &str) -> Option<NaiveDate> {
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(field, format).ok())
+}
+
+/// A date at the start of `field`, like `Apr 18, 1990` in `Apr 18, 1990 (29)`.
+pub fn parse_leading_date(field: &str) -> Option<NaiveDate> {
+    DATE_FORMATS.iter().find_map(|format| match NaiveDate::parse_and_remainder(field, format) {
+        Ok((date, rest)) if rest.is_empty() || rest.starts_with(' ') => Some(date),
+        _ => None,
+    })
+}
+
+/// The narrowest type `field` fits in, `None` for empty fields.
+fn detect(field: &str) -> Option<ColumnType> {
+    if field.is_empty() {
+        return None;
+    }
+    // numbers with leading zeros are codes (zip, phone, ids), keep them as strings
+    let digits = field.trim_start_matches(['-', '+']);
+    let leading_zero = digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.");
+    let ty = [ColumnType::Int, ColumnType::Float, ColumnType::Bool, ColumnType::Date]
+        .into_iter()
+        .filter(|ty| !(leading_zero && matches!(ty, ColumnType::Int | ColumnType::Float)))
+        .find(|ty| match ty {
+            // a whole field only, text after a date is fine once a column is typed as dates
+            ColumnType::Date => parse_date(field).is_some(),
+            ty => parse_field(field, *ty).is_some(),
+        })
+        .unwrap_or(ColumnType::String);
+    Some(ty)
+}
+
+fn merge(a: ColumnType, b: ColumnType) -> ColumnType {
+    match (a, b) {
+        _ if a == b => a,
+        (ColumnType::Int, ColumnType::Float) | (ColumnType::Float, ColumnType::Int) => ColumnType::Float,
+        _ => ColumnType::String,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sample(rows: &[&[&str]]) -> Vec<StringRecord> {
+        rows.iter().map(|row| StringRecord::from(row.to_vec())).collect()
+    }
+
+    #[test]
+    fn test_infer_column_types() -> Result<()> {
+        let headers = ["id", "score", "active", "joined", "zip", "name", "empty"].map(String::from);
+        let rows = sample(&[
+            &["1", "1.5", "true", "2019-06-01", "01234", "Buffon", ""],
+            &["2", "2", "FALSE", "2020-01-31", "10121", "Dybala", ""],
+            &["", "", "", "", "", "", ""],
+        ]);
+        let columns = TypedColumns::infer(&headers, &rows, &CsvTypes::default())?;
+        assert_eq!(
+            columns.columns.iter().map(|c| c.ty).collect::<Vec<_>>(),
+            vec![
+                ColumnType::Int,
+                ColumnType::Float,
+                ColumnType::Bool,
+                ColumnType::Date,
+                ColumnType::String,
+                ColumnType::String,
+                ColumnType::String,
+            ]
+        );
+        assert_eq!(columns.value(0, "10")?, json!(10));
+        assert_eq!(columns.value(0, "")?, Value::Null);
+        assert_eq!(columns.value(0, "n/a")?, json!("n/a"));
+        assert_eq!(columns.value(1, "2")?, json!(2.0));
+        assert_eq!(columns.value(2, "False")?, json!(false));
+        assert_eq!(columns.value(3, "2019/06/01")?, json!("2019-06-01"));
+        assert_eq!(columns.value(4, "01234")?, json!("01234"));
+        assert_eq!(columns.value(9, "")?, Value::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn test_type_overrides() -> Result<()> {
+        let headers = ["Kit Number", "DOB"].map(String::from);
+        let rows = sample(&[&["10", "Apr 18, 1990"]]);
+        let opts = CsvTypes {
+            infer: false,
+            overrides: vec![("Kit Number".into(), ColumnType::Int), ("DOB".into(), ColumnType::Date)],
+            ..Default::default()
+        };
+        let columns = TypedColumns::infer(&headers, &rows, &opts)?;
+        assert_eq!(columns.value(0, "10")?, json!(10));
+        assert_eq!(columns.value(1, "Apr 18, 1990")?, json!("1990-04-18"));
+        assert!(columns.value(0, "ten").is_err());
+
+        let opts = CsvTypes {
+            overrides: vec![("Missing".into(), ColumnType::Int)],
+            ..Default::default()
+        };
+        assert!(TypedColumns::infer(&headers, &rows, &opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_date_override_juventus() -> Result<()> {
+        let types = CsvTypes { overrides: vec![("DOB".into(), ColumnType::Date)], ..Default::default() };
+        let file = std::fs::File::open("assets/juventus.csv")?;
+        let stream = crate::process::csv_convert::csv_records(file, &Default::default(), &types)?;
+        let records = stream.records.collect::<Result<Vec<_>>>()?;
+        assert_eq!(records[0]["DOB"], json!("1990-04-18"));
+        assert!(records.iter().all(|r| r["DOB"].as_str().is_some_and(|dob| parse_date(dob).is_some())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_infer_keeps_strings() -> Result<()> {
+        let headers = ["n"].map(String::from);
+        let opts = CsvTypes { infer: false, ..Default::default() };
+        let columns = TypedColumns::infer(&headers, &sample(&[&["1"]]), &opts)?;
+        assert_eq!(columns.value(0, "1")?, json!("1"));
+        assert_eq!(columns.value(0, "")?, json!(""));
+        Ok(())
+    }
+}
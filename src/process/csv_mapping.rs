@@ -0,0 +1,355 @@
+use std::{collections::BTreeMap, fs};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::cli::ColumnType;
+use super::{
+    csv_convert::{Record, RecordStream},
+    csv_infer::parse_field,
+    csv_reject::RejectedRecord,
+    csv_writer::cell_text,
+};
+
+/// How columns are renamed, retyped, filled, derived and dropped before records are
+/// written, read from TOML or JSON. Columns are named as in the input throughout.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mapping {
+    /// Case of the names not set through `columns`
+    pub case: Option<NameCase>,
+    #[serde(default)]
+    pub drop: Vec<String>,
+    #[serde(default)]
+    pub columns: BTreeMap<String, ColumnMapping>,
+    /// Columns added after the others, in this order
+    #[serde(default)]
+    pub derive: Vec<DerivedColumn>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnMapping {
+    /// New name of the column
+    pub name: Option<String>,
+    /// Type the values are cast to, from their text
+    #[serde(rename = "type", default, deserialize_with = "cast_type")]
+    pub ty: Option<ColumnType>,
+    /// Value of the rows where the column is empty
+    pub default: Option<Value>,
+}
+
+/// A column made from a template like `"{Name} ({Position})"`, `{{` and `}}` are literal braces.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DerivedColumn {
+    pub name: String,
+    pub template: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NameCase {
+    /// `kit_number`
+    Snake,
+    /// `kitNumber`
+    Camel,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Column(String),
+}
+
+/// Records of `stream` reshaped by `mapping`. Mapped columns must exist in the input, and
+/// no two columns may end up with the same name.
+pub fn map_records<'a>(stream: RecordStream<'a>, mapping: Mapping) -> Result<RecordStream<'a>> {
+    let known = |name: &String| match stream.headers.contains(name) {
+        true => Ok(()),
+        false => Err(anyhow::anyhow!("Unknown column {:?} in the mapping", name)),
+    };
+    mapping.drop.iter().chain(mapping.columns.keys()).try_for_each(known)?;
+    let derived = mapping
+        .derive
+        .iter()
+        .map(|column| {
+            let pieces = parse_template(&column.template).with_context(|| format!("Invalid template of {:?}", column.name))?;
+            pieces.iter().try_for_each(|piece| match piece {
+                Piece::Column(name) => known(name),
+                Piece::Text(_) => Ok(()),
+            })?;
+            Ok((column.name.clone(), pieces))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let kept: Vec<(String, String)> = stream
+        .headers
+        .iter()
+        .filter(|header| !mapping.drop.contains(header))
+        .map(|header| (header.clone(), mapping.name(header)))
+        .collect();
+    let mut headers: Vec<String> = Vec::new();
+    for name in kept.iter().map(|(_, name)| name).chain(derived.iter().map(|(name, _)| name)) {
+        if headers.contains(name) {
+            anyhow::bail!("Column {:?} appears twice after mapping", name);
+        }
+        headers.push(name.clone());
+    }
+
+    let dates = kept
+        .iter()
+        .filter(|(from, _)| match mapping.columns.get(from).and_then(|c| c.ty) {
+            Some(ty) => ty == ColumnType::Date,
+            None => stream.dates.contains(from),
+        })
+        .map(|(_, to)| to.clone())
+        .collect();
+    let columns = mapping.columns;
+    let input = stream.headers;
+    let records = stream.records.map(move |record| {
+        let mut record = record?;
+        // a value that doesn't cast rejects the row, as it was read
+        let values = columns
+            .iter()
+            .map(|(name, column)| {
+                let value = record.get(name).unwrap_or(&Value::Null);
+                let value = column.default.as_ref().filter(|_| is_empty(value)).unwrap_or(value);
+                match column.ty {
+                    Some(ty) => cast(value, ty).with_context(|| format!("Cannot map column {:?}", name)),
+                    None => Ok(value.clone()),
+                }
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| RejectedRecord::from_record(&input, &record, e))?;
+        record.extend(columns.keys().cloned().zip(values));
+        // derived columns see the values before renames, but go last
+        let values: Vec<Value> = derived.iter().map(|(_, pieces)| Value::String(render(pieces, &record))).collect();
+        let mut mapped: Record = kept.iter().map(|(from, to)| (to.clone(), record.remove(from).unwrap_or(Value::Null))).collect();
+        mapped.extend(derived.iter().map(|(name, _)| name.clone()).zip(values));
+        Ok(mapped)
+    });
+    Ok(RecordStream { headers, dates, records: Box::new(records) })
+}
+
+impl Mapping {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| format!("Cannot read mapping {}", path))?;
+        if path.to_ascii_lowercase().ends_with(".json") {
+            serde_json::from_str(&content).with_context(|| format!("Invalid mapping {}", path))
+        } else {
+            toml::from_str(&content).with_context(|| format!("Invalid mapping {}", path))
+        }
+    }
+
+    /// Output name of the input column `header`.
+    fn name(&self, header: &str) -> String {
+        match (self.columns.get(header).and_then(|c| c.name.as_ref()), self.case) {
+            (Some(name), _) => name.clone(),
+            (None, Some(case)) => case.apply(header),
+            (None, None) => header.to_string(),
+        }
+    }
+}
+
+impl NameCase {
+    pub fn apply(self, name: &str) -> String {
+        let words = words(name);
+        match self {
+            NameCase::Snake => words.join("_"),
+            NameCase::Camel => words
+                .iter()
+                .enumerate()
+                .map(|(i, word)| {
+                    let mut chars = word.chars();
+                    match (i, chars.next()) {
+                        (0, _) | (_, None) => word.clone(),
+                        (_, Some(first)) => first.to_uppercase().chain(chars).collect(),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The lowercase words of a name: `Kit Number`, `kit_number`, `kitNumber` all give `kit`, `number`.
+/// An uppercase run is one word, as in `DOB` or the `http` of `HTTPServer`.
+fn words(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            words.extend((!word.is_empty()).then(|| std::mem::take(&mut word)));
+            continue;
+        }
+        let prev = i.checked_sub(1).map(|i| chars[i]);
+        let next = chars.get(i + 1);
+        let starts_word = c.is_uppercase()
+            && prev.is_some_and(|p| p.is_lowercase() || (p.is_uppercase() && next.is_some_and(|n| n.is_lowercase())));
+        if starts_word && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.extend(c.to_lowercase());
+    }
+    words.extend((!word.is_empty()).then_some(word));
+    words
+}
+
+fn parse_template(template: &str) -> Result<Vec<Piece>> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => text.push(chars.next().unwrap_or(c)),
+            '}' if chars.peek() == Some(&'}') => text.push(chars.next().unwrap_or(c)),
+            '{' => {
+                let mut name = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(c);
+                }
+                if !closed {
+                    anyhow::bail!("Missing '}}' after {{{}", name);
+                }
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(Piece::Column(name));
+            }
+            '}' => anyhow::bail!("Unexpected '}}', write '}}}}' for a brace"),
+            _ => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+fn render(pieces: &[Piece], record: &Record) -> String {
+    pieces
+        .iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => text.as_str().into(),
+            Piece::Column(name) => record.get(name).map(cell_text).unwrap_or_default(),
+        })
+        .collect()
+}
+
+fn is_empty(value: &Value) -> bool {
+    matches!(value, Value::Null) || value.as_str() == Some("")
+}
+
+/// `value` as `ty`, parsed from its text. Empty values are null unless cast to a string.
+fn cast(value: &Value, ty: ColumnType) -> Result<Value> {
+    let text = cell_text(value);
+    let cast = match (value, ty) {
+        (Value::Null, _) => Some(Value::Null),
+        (_, ColumnType::String) => Some(Value::String(text.into_owned())),
+        (_, _) if text.is_empty() => Some(Value::Null),
+        (_, _) => parse_field(&text, ty),
+    };
+    cast.ok_or_else(|| anyhow::anyhow!("Cannot cast {} to {}", value, ty))
+}
+
+fn cast_type<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<ColumnType>, D::Error> {
+    let ty = String::deserialize(deserializer)?;
+    ty.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn map(mapping: &str, records: Value) -> Result<(Vec<String>, Vec<Value>)> {
+        let records: Vec<Record> = records.as_array().unwrap().iter().map(|r| r.as_object().unwrap().clone()).collect();
+        let stream = RecordStream {
+            headers: records[0].keys().cloned().collect(),
+            dates: Vec::new(),
+            records: Box::new(records.into_iter().map(Ok)),
+        };
+        let mapped = map_records(stream, toml::from_str(mapping)?)?;
+        let records = mapped.records.map(|r| r.map(Value::Object)).collect::<Result<_>>()?;
+        Ok((mapped.headers, records))
+    }
+
+    #[test]
+    fn test_name_case() {
+        assert_eq!(NameCase::Snake.apply("Kit Number"), "kit_number");
+        assert_eq!(NameCase::Camel.apply("Kit Number"), "kitNumber");
+        assert_eq!(NameCase::Snake.apply("DOB"), "dob");
+        assert_eq!(NameCase::Snake.apply("playerId"), "player_id");
+        assert_eq!(NameCase::Camel.apply("HTTPServer-name"), "httpServerName");
+        assert_eq!(NameCase::Camel.apply("kit_number_2"), "kitNumber2");
+    }
+
+    #[test]
+    fn test_template() -> Result<()> {
+        assert_eq!(
+            parse_template("{Name} ({Position}) {{x}}")?,
+            vec![
+                Piece::Column("Name".into()),
+                Piece::Text(" (".into()),
+                Piece::Column("Position".into()),
+                Piece::Text(") {x}".into()),
+            ]
+        );
+        assert!(parse_template("{Name").is_err());
+        assert!(parse_template("Name}").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_records() -> Result<()> {
+        let mapping = r#"
+            case = "snake"
+            drop = ["Nationality"]
+
+            [columns.DOB]
+            name = "born"
+            type = "date"
+
+            [columns."Kit Number"]
+            type = "string"
+            default = "none"
+
+            [[derive]]
+            name = "label"
+            template = "{Name} ({Position})"
+        "#;
+        let (headers, records) = map(
+            mapping,
+            json!([
+                {"Name": "Buffon", "Position": "Goalkeeper", "DOB": "Jan 28, 1978 (41)", "Nationality": "Italy", "Kit Number": 77},
+                {"Name": "Dybala", "Position": "Forward", "DOB": "", "Nationality": "Argentina", "Kit Number": null},
+            ]),
+        )?;
+        assert_eq!(headers, vec!["name", "position", "born", "kit_number", "label"]);
+        assert_eq!(
+            records[0],
+            json!({"name": "Buffon", "position": "Goalkeeper", "born": "1978-01-28", "kit_number": "77", "label": "Buffon (Goalkeeper)"})
+        );
+        assert_eq!(records[1]["born"], Value::Null);
+        assert_eq!(records[1]["kit_number"], "none");
+        assert_eq!(Mapping::load("assets/juventus.mapping.toml")?.derive.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_errors() {
+        let record = json!([{"Name": "Buffon", "Kit Number": "x"}]);
+        assert!(map("drop = [\"Club\"]", record.clone()).is_err());
+        assert!(map("[[derive]]\nname = \"a\"\ntemplate = \"{Club}\"", record.clone()).is_err());
+        assert!(map("[columns.Name]\nname = \"Kit Number\"", record.clone()).is_err());
+        let err = map("[columns.\"Kit Number\"]\ntype = \"int\"", record.clone()).unwrap_err();
+        assert_eq!(err.downcast_ref::<RejectedRecord>().map(|r| r.fields.clone()), Some(vec!["Buffon".into(), "x".into()]));
+        assert!(map("[columns.Name]\ntype = \"number\"", record).is_err());
+    }
+}
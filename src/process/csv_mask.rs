@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD as URL_SAFE_NO_PAD, Engine};
+use serde_json::Value;
+
+use crate::cli::{CsvSource, CsvTypes, MaskStrategy, OutputOpts};
+use super::{
+    csv_convert::{source_records, RecordStream},
+    csv_infer::parse_leading_date,
+    csv_nested::reshape_records,
+    csv_writer::{cell_text, write_csv_stream},
+    text::{Blake3, KeyLoader, TextSign},
+};
+
+const REDACTED: &str = "***";
+/// Bytes of the keyed hash kept in a pseudonym, 16 characters once encoded
+const TOKEN_BYTES: usize = 12;
+
+pub fn process_csv_mask(source: &CsvSource, masks: &[(String, MaskStrategy)], key: Option<&str>, output: &str, out: &OutputOpts) -> Result<()> {
+    // cells are read as text, so the columns left alone are written back as they were
+    let source = CsvSource { types: CsvTypes { infer: false, ..source.types.clone() }, ..source.clone() };
+    let stream = source_records(&source, &out.root)?;
+    let signer = match key {
+        Some(key) => Some(Blake3::load(key).with_context(|| format!("Cannot load key {}", key))?),
+        None => None,
+    };
+    let stream = mask_records(stream, masks, signer)?;
+    write_csv_stream(reshape_records(stream, out)?, output, out)
+}
+
+/// Apply the `masks` to every record, `signer` is required to pseudonymize.
+pub fn mask_records<'a>(stream: RecordStream<'a>, masks: &[(String, MaskStrategy)], signer: Option<Blake3>) -> Result<RecordStream<'a>> {
+    for (column, strategy) in masks {
+        if !stream.headers.contains(column) {
+            anyhow::bail!("Unknown column {:?}", column);
+        }
+        if *strategy == MaskStrategy::Pseudonymize && signer.is_none() {
+            anyhow::bail!("--key is needed to pseudonymize {:?}", column);
+        }
+    }
+    let masks = masks.to_vec();
+    let records = stream.records.enumerate().map(move |(i, record)| {
+        let mut record = record?;
+        for (column, strategy) in &masks {
+            if let Some(value) = record.get_mut(column) {
+                *value = mask_value(value, *strategy, signer.as_ref())
+                    .with_context(|| format!("Cannot mask row {}, column {:?}", i + 1, column))?;
+            }
+        }
+        Ok(record)
+    });
+    Ok(RecordStream { headers: stream.headers, dates: stream.dates, records: Box::new(records) })
+}
+
+/// Empty values stay empty, there is nothing to hide and it keeps nulls distinguishable.
+/// Values that are not dates are left as they are by `Year`.
+fn mask_value(value: &Value, strategy: MaskStrategy, signer: Option<&Blake3>) -> Result<Value> {
+    let text = cell_text(value);
+    if text.is_empty() {
+        return Ok(value.clone());
+    }
+    match strategy {
+        MaskStrategy::Redact => Ok(Value::String(REDACTED.into())),
+        MaskStrategy::Truncate(n) => Ok(Value::String(text.chars().take(n).collect())),
+        MaskStrategy::Year => {
+            let year = parse_leading_date(&text).map(|date| chrono::Datelike::year(&date));
+            Ok(year.map_or_else(|| value.clone(), Value::from))
+        }
+        MaskStrategy::Pseudonymize => {
+            let signer = signer.ok_or_else(|| anyhow::anyhow!("Missing key"))?;
+            let hash = signer.sign(&mut text.as_bytes())?;
+            Ok(Value::String(URL_SAFE_NO_PAD.encode(&hash[..TOKEN_BYTES])))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn test_mask_values() -> Result<()> {
+        assert_eq!(mask_value(&json!("1978-01-28"), MaskStrategy::Year, None)?, json!(1978));
+        assert_eq!(mask_value(&json!("Jan 28, 1978 (41)"), MaskStrategy::Year, None)?, json!(1978));
+        assert_eq!(mask_value(&json!("soon"), MaskStrategy::Year, None)?, json!("soon"));
+        assert_eq!(mask_value(&json!("Bonucci"), MaskStrategy::Truncate(3), None)?, json!("Bon"));
+        assert_eq!(mask_value(&json!(19), MaskStrategy::Redact, None)?, json!(REDACTED));
+        assert_eq!(mask_value(&Value::Null, MaskStrategy::Redact, None)?, Value::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mask_csv() -> Result<()> {
+        let dir = TempDir::new("mask")?;
+        let path = |name: &str| dir.path(name);
+        fs::write(path("in.csv"), "Name,DOB,Height,Captain\nBuffon,1978/01/28,1.90,TRUE\nDybala,unknown,01.77,false\n")?;
+        let source = CsvSource { input: path("in.csv"), from: None, dialect: Default::default(), types: Default::default() };
+        let masks = [("DOB".to_string(), MaskStrategy::Year)];
+        // csv unless asked otherwise, even when the output name doesn't say
+        process_csv_mask(&source, &masks, None, &path("out"), &OutputOpts::default())?;
+        assert_eq!(fs::read_to_string(path("out"))?, "Name,DOB,Height,Captain\nBuffon,1978,1.90,TRUE\nDybala,unknown,01.77,false\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_pseudonymize() -> Result<()> {
+        let signer = Blake3::load("fixtures/blake3.txt")?;
+        let buffon = mask_value(&json!("Buffon"), MaskStrategy::Pseudonymize, Some(&signer))?;
+        assert_eq!(buffon, mask_value(&json!("Buffon"), MaskStrategy::Pseudonymize, Some(&signer))?);
+        assert_ne!(buffon, mask_value(&json!("Perin"), MaskStrategy::Pseudonymize, Some(&signer))?);
+        assert_eq!(buffon.as_str().map(str::len), Some(16));
+
+        let other = Blake3::new([7; 32]);
+        assert_ne!(buffon, mask_value(&json!("Buffon"), MaskStrategy::Pseudonymize, Some(&other))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_short_key() -> Result<()> {
+        let dir = TempDir::new("mask-key")?;
+        fs::write(dir.path("key"), b"short")?;
+        let err = Blake3::load(dir.path("key")).err().map(|e| e.to_string());
+        assert_eq!(err.as_deref(), Some("The key must be at least 32 bytes, not 5"));
+        Ok(())
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde_json::{Map, Value};
+
+use crate::cli::OutputOpts;
+use super::csv_convert::{Record, RecordStream};
+
+/// Highest array index of an `--unflatten` header, the items up to it are allocated.
+const MAX_INDEX: usize = 9_999;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Apply `--flatten` or `--unflatten` to records on their way to the writer.
+pub fn reshape_records<'a>(stream: RecordStream<'a>, out: &OutputOpts) -> Result<RecordStream<'a>> {
+    match (out.flatten, out.unflatten) {
+        (true, _) => flatten_records(stream),
+        (_, true) => unflatten_records(stream),
+        _ => Ok(stream),
+    }
+}
+
+/// Rebuild nested objects and arrays from header paths like `address.city` and `tags[0]`.
+/// Trailing nulls of arrays are dropped, so rows with fewer items get shorter arrays.
+/// Items before the highest index are null, which bounds the index to `MAX_INDEX`.
+pub fn unflatten_records(stream: RecordStream<'_>) -> Result<RecordStream<'_>> {
+    let paths: Vec<Vec<Segment>> = stream.headers.iter().map(|h| parse_path(h)).collect();
+    for (header, path) in stream.headers.iter().zip(&paths) {
+        if let Some(i) = path.iter().find_map(|s| match s {
+            Segment::Index(i) if *i > MAX_INDEX => Some(i),
+            _ => None,
+        }) {
+            anyhow::bail!("Column {:?} indexes item {} of an array, the most is {}", header, i, MAX_INDEX);
+        }
+    }
+    let mut headers: Vec<String> = Vec::new();
+    for path in &paths {
+        if let Some(Segment::Key(key)) = path.first() {
+            if !headers.contains(key) {
+                headers.push(key.clone());
+            }
+        }
+    }
+    let columns = stream.headers;
+    let records = stream.records.map(move |record| {
+        let mut record = record?;
+        let mut root = Value::Object(Map::new());
+        for (column, path) in columns.iter().zip(&paths) {
+            let value = record.remove(column).unwrap_or(Value::Null);
+            insert(&mut root, path, value).map_err(|e| anyhow::anyhow!("Column {:?} {}", column, e))?;
+        }
+        trim_arrays(&mut root);
+        match root {
+            Value::Object(record) => Ok(record),
+            _ => unreachable!("the root is always an object"),
+        }
+    });
+    Ok(RecordStream { headers, dates: stream.dates, records: Box::new(records) })
+}
+
+/// Spread nested objects and arrays over `key.sub` and `key[i]` columns. Arrays
+/// differ in length between records, so every record is read before the header is known.
+pub fn flatten_records(stream: RecordStream<'_>) -> Result<RecordStream<'_>> {
+    // flattened column names grouped by the column they come from, to keep them together
+    let mut groups: Vec<Vec<String>> = vec![Vec::new(); stream.headers.len()];
+    let mut group_of: HashMap<String, usize> =
+        stream.headers.iter().enumerate().map(|(i, h)| (h.clone(), i)).collect();
+    let mut records = Vec::new();
+    for record in stream.records {
+        let mut flat = Record::new();
+        for (key, value) in record? {
+            let start = flat.len();
+            flatten_value(key.clone(), value, &mut flat);
+            // keys missing from the headers get one group, the first time they show up
+            let i = *group_of.entry(key).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            for name in flat.keys().skip(start) {
+                if !groups[i].contains(name) {
+                    groups[i].push(name.clone());
+                }
+            }
+        }
+        records.push(Ok(flat));
+    }
+    Ok(RecordStream {
+        headers: groups.into_iter().flatten().collect(),
+        dates: stream.dates,
+        records: Box::new(records.into_iter()),
+    })
+}
+
+fn flatten_value(key: String, value: Value, out: &mut Record) {
+    match value {
+        Value::Object(object) if !object.is_empty() => {
+            for (k, v) in object {
+                flatten_value(format!("{}.{}", key, k), v, out);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (i, v) in items.into_iter().enumerate() {
+                flatten_value(format!("{}[{}]", key, i), v, out);
+            }
+        }
+        Value::Object(_) | Value::Array(_) => {
+            out.insert(key, Value::Null);
+        }
+        value => {
+            out.insert(key, value);
+        }
+    }
+}
+
+/// `a.b[0][1].c` is `a`, `b`, 0, 1, `c`. A header that isn't a well formed path is a plain key.
+fn parse_path(header: &str) -> Vec<Segment> {
+    let mut path = Vec::new();
+    for part in header.split('.') {
+        let (key, mut rest) = part.split_at(part.find('[').unwrap_or(part.len()));
+        if key.is_empty() && (path.is_empty() || rest.is_empty()) {
+            return vec![Segment::Key(header.to_string())];
+        }
+        if !key.is_empty() {
+            path.push(Segment::Key(key.to_string()));
+        }
+        while !rest.is_empty() {
+            let index = rest
+                .strip_prefix('[')
+                .and_then(|r| r.split_once(']'))
+                .and_then(|(n, r)| Some((n.parse::<usize>().ok()?, r)));
+            match index {
+                Some((n, r)) => {
+                    path.push(Segment::Index(n));
+                    rest = r;
+                }
+                None => return vec![Segment::Key(header.to_string())],
+            }
+        }
+    }
+    path
+}
+
+fn insert(node: &mut Value, path: &[Segment], value: Value) -> Result<()> {
+    let Some((segment, rest)) = path.split_first() else {
+        *node = value;
+        return Ok(());
+    };
+    let child = match (segment, node) {
+        (Segment::Key(key), Value::Object(object)) => object.entry(key.clone()).or_insert(Value::Null),
+        (Segment::Index(i), Value::Array(items)) => {
+            if items.len() <= *i {
+                items.resize(i + 1, Value::Null);
+            }
+            &mut items[*i]
+        }
+        _ => anyhow::bail!("conflicts with another column"),
+    };
+    if child.is_null() && !rest.is_empty() {
+        *child = match rest[0] {
+            Segment::Key(_) => Value::Object(Map::new()),
+            Segment::Index(_) => Value::Array(Vec::new()),
+        };
+    }
+    insert(child, rest, value)
+}
+
+fn trim_arrays(value: &mut Value) {
+    match value {
+        Value::Object(object) => object.values_mut().for_each(trim_arrays),
+        Value::Array(items) => {
+            while items.last().is_some_and(Value::is_null) {
+                items.pop();
+            }
+            items.iter_mut().for_each(trim_arrays);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn stream(headers: &[&str], records: Value) -> RecordStream<'static> {
+        let records: Vec<Record> = records.as_array().unwrap().iter().map(|r| r.as_object().unwrap().clone()).collect();
+        RecordStream {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            dates: Vec::new(),
+            records: Box::new(records.into_iter().map(Ok)),
+        }
+    }
+
+    #[test]
+    fn test_parse_path() {
+        use Segment::*;
+        assert_eq!(parse_path("address.city"), vec![Key("address".into()), Key("city".into())]);
+        assert_eq!(parse_path("tags[0]"), vec![Key("tags".into()), Index(0)]);
+        assert_eq!(parse_path("a[1][2].b"), vec![Key("a".into()), Index(1), Index(2), Key("b".into())]);
+        assert_eq!(parse_path("Kit Number"), vec![Key("Kit Number".into())]);
+        assert_eq!(parse_path("a[x]"), vec![Key("a[x]".into())]);
+        assert_eq!(parse_path("[0]"), vec![Key("[0]".into())]);
+    }
+
+    #[test]
+    fn test_unflatten() -> Result<()> {
+        let input = stream(
+            &["name", "address.city", "address.zip", "tags[0]", "tags[1]"],
+            json!([
+                {"name": "Buffon", "address.city": "Turin", "address.zip": "10121", "tags[0]": "gk", "tags[1]": "captain"},
+                {"name": "Dybala", "address.city": null, "address.zip": null, "tags[0]": "fw", "tags[1]": null},
+            ]),
+        );
+        let output = unflatten_records(input)?;
+        assert_eq!(output.headers, vec!["name", "address", "tags"]);
+        let records = output.records.collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            Value::Array(records.into_iter().map(Value::Object).collect()),
+            json!([
+                {"name": "Buffon", "address": {"city": "Turin", "zip": "10121"}, "tags": ["gk", "captain"]},
+                {"name": "Dybala", "address": {"city": null, "zip": null}, "tags": ["fw"]},
+            ])
+        );
+
+        let conflict = stream(&["a", "a.b"], json!([{"a": 1, "a.b": 2}]));
+        assert!(unflatten_records(conflict)?.records.next().unwrap().is_err());
+
+        let huge = stream(&["tags[999999999999]"], json!([{"tags[999999999999]": "gk"}]));
+        assert!(unflatten_records(huge).is_err());
+        let sparse = stream(&["id", "tags[5]"], json!([{"id": 1, "tags[5]": "captain"}]));
+        let records = unflatten_records(sparse)?.records.collect::<Result<Vec<_>>>()?;
+        assert_eq!(records[0]["tags"], json!([null, null, null, null, null, "captain"]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten() -> Result<()> {
+        let input = stream(
+            &["name", "tags", "club"],
+            json!([
+                {"name": "Buffon", "tags": ["gk"], "club": {"name": "Juventus"}},
+                {"name": "Dybala", "tags": ["fw", "10"], "club": {}},
+            ]),
+        );
+        let output = flatten_records(input)?;
+        assert_eq!(output.headers, vec!["name", "tags[0]", "tags[1]", "club.name", "club"]);
+        let records = output.records.collect::<Result<Vec<_>>>()?;
+        assert_eq!(records[1]["tags[1]"], json!("10"));
+        assert_eq!(records[1]["club"], Value::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_extra_keys() -> Result<()> {
+        let input = stream(
+            &["name"],
+            json!([
+                {"name": "Buffon", "club": {"name": "Juventus"}},
+                {"name": "Dybala", "club": {"name": "Roma"}},
+            ]),
+        );
+        let output = flatten_records(input)?;
+        assert_eq!(output.headers, vec!["name", "club.name"]);
+        Ok(())
+    }
+}
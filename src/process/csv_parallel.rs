@@ -0,0 +1,383 @@
+use std::{
+    collections::BTreeMap,
+    io::{ErrorKind, Read},
+    mem,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+use anyhow::{Context, Result};
+use csv::StringRecord;
+
+use crate::cli::{CsvDialect, CsvTypes};
+use super::{
+    csv_convert::{infer_columns, string_record, type_record, Record, RecordStream},
+    csv_infer::TypedColumns,
+};
+
+/// Bytes of input handed to a worker at a time.
+const CHUNK_SIZE: usize = 4 << 20;
+const READ_SIZE: usize = 256 << 10;
+
+/// Typed records of a CSV input, parsed and typed by `jobs` threads. The input is cut
+/// into chunks ending on a record boundary, and records come out in input order.
+pub fn parallel_csv_records<'a>(reader: Box<dyn Read>, dialect: &CsvDialect, types: &CsvTypes, jobs: usize) -> Result<RecordStream<'a>> {
+    chunked_csv_records(reader, dialect, types, jobs, CHUNK_SIZE)
+}
+
+fn chunked_csv_records<'a>(
+    reader: Box<dyn Read>,
+    dialect: &CsvDialect,
+    types: &CsvTypes,
+    jobs: usize,
+    chunk_size: usize,
+) -> Result<RecordStream<'a>> {
+    let mut chunker = Chunker::new(reader, dialect);
+    // the first chunk holds the header and every row column types are inferred from
+    let first = chunker.next_chunk(chunk_size, types.sample_rows + 1)?.unwrap_or_default();
+    let mut reader = dialect.reader_builder().flexible(true).from_reader(&first.data[..]);
+    let mut headers = dialect.headers(&mut reader)?;
+    let width = dialect.width(&mut reader)?;
+    let mut records = reader.into_byte_records().map(|result| string_record(result?, width));
+    let (sample, columns) = infer_columns(&mut headers, &mut records, types)?;
+    let dates = columns.dates();
+    let typer = Arc::new(ChunkTyper { dialect: dialect.clone(), headers: headers.clone(), columns, width });
+    let current = typer.type_records(sample.into_iter().chain(records));
+
+    if chunker.is_done() {
+        return Ok(RecordStream { headers, dates, records: Box::new(current.into_iter()) });
+    }
+    let (work, queue) = mpsc::channel::<Chunk>();
+    let queue = Arc::new(Mutex::new(queue));
+    let (done, results) = mpsc::channel();
+    for _ in 0..jobs {
+        let (queue, done, typer) = (queue.clone(), done.clone(), typer.clone());
+        thread::spawn(move || loop {
+            let chunk = match queue.lock().map(|queue| queue.recv()) {
+                Ok(Ok(chunk)) => chunk,
+                _ => break,
+            };
+            if done.send((chunk.index, typer.type_chunk(&chunk))).is_err() {
+                break;
+            }
+        });
+    }
+    let records = OrderedRecords {
+        chunker,
+        chunk_size,
+        work,
+        results,
+        pending: BTreeMap::new(),
+        current: current.into_iter(),
+        next: 1,
+        sent: 1,
+        window: jobs * 2,
+    };
+    Ok(RecordStream { headers, dates, records: Box::new(records) })
+}
+
+/// A slice of the input made of whole records.
+#[derive(Debug, Default)]
+struct Chunk {
+    index: usize,
+    /// Line number of the first byte
+    first_line: u64,
+    data: Vec<u8>,
+}
+
+/// Reads the input a chunk at a time, tracking the quoting state to only cut between records.
+struct Chunker {
+    reader: Box<dyn Read>,
+    buf: Vec<u8>,
+    scanned: usize,
+    records: usize,
+    scanner: Scanner,
+    eof: bool,
+    index: usize,
+    lines: u64,
+}
+
+impl Chunker {
+    fn new(reader: Box<dyn Read>, dialect: &CsvDialect) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            scanned: 0,
+            records: 0,
+            scanner: Scanner::new(dialect),
+            eof: false,
+            index: 0,
+            lines: 0,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.eof && self.buf.is_empty()
+    }
+
+    /// The next chunk of at least `size` bytes and `min_records` records, unless the input ends first.
+    fn next_chunk(&mut self, size: usize, min_records: usize) -> Result<Option<Chunk>> {
+        loop {
+            while self.scanned < self.buf.len() {
+                let byte = self.buf[self.scanned];
+                self.scanned += 1;
+                if self.scanner.feed(byte) {
+                    self.records += 1;
+                    if self.scanned >= size && self.records >= min_records {
+                        return Ok(Some(self.cut(self.scanned)));
+                    }
+                }
+            }
+            if self.eof {
+                return Ok((!self.buf.is_empty()).then(|| self.cut(self.buf.len())));
+            }
+            let len = self.buf.len();
+            self.buf.resize(len + READ_SIZE, 0);
+            match self.reader.read(&mut self.buf[len..]) {
+                Ok(n) => {
+                    self.buf.truncate(len + n);
+                    self.eof = n == 0;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => self.buf.truncate(len),
+                Err(e) => {
+                    self.buf.truncate(len);
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    fn cut(&mut self, at: usize) -> Chunk {
+        let rest = self.buf.split_off(at);
+        let data = mem::replace(&mut self.buf, rest);
+        let chunk = Chunk { index: self.index, first_line: self.lines + 1, data };
+        self.index += 1;
+        self.lines += chunk.data.iter().filter(|&&b| b == b'\n').count() as u64;
+        self.scanned = 0;
+        self.records = 0;
+        chunk
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanState {
+    RecordStart,
+    FieldStart,
+    Unquoted,
+    Quoted,
+    Escaped,
+    QuoteInQuoted,
+    Comment,
+}
+
+/// Follows the quoting rules of the CSV parser byte by byte, so a newline inside a
+/// quoted field is not taken for the end of a record.
+struct Scanner {
+    state: ScanState,
+    delimiter: u8,
+    quote: u8,
+    escape: Option<u8>,
+    comment: Option<u8>,
+}
+
+impl Scanner {
+    fn new(dialect: &CsvDialect) -> Self {
+        Self {
+            state: ScanState::RecordStart,
+            delimiter: dialect.delimiter,
+            quote: dialect.quote,
+            escape: dialect.escape,
+            comment: dialect.comment,
+        }
+    }
+
+    /// Returns true when `byte` ends a record, blank and comment lines aside.
+    fn feed(&mut self, byte: u8) -> bool {
+        use ScanState::*;
+        let (state, end) = match (self.state, byte) {
+            (Quoted, b) if Some(b) == self.escape => (Escaped, false),
+            (Quoted, b) if b == self.quote => (QuoteInQuoted, false),
+            (Quoted, _) | (Escaped, _) => (Quoted, false),
+            (Comment, b'\n') => (RecordStart, false),
+            (Comment, _) => (Comment, false),
+            (RecordStart, b'\n' | b'\r') => (RecordStart, false),
+            (RecordStart, b) if Some(b) == self.comment => (Comment, false),
+            (_, b'\n') => (RecordStart, true),
+            (RecordStart | FieldStart, b) if b == self.quote => (Quoted, false),
+            (QuoteInQuoted, b) if b == self.quote => (Quoted, false),
+            (_, b) if b == self.delimiter => (FieldStart, false),
+            _ => (Unquoted, false),
+        };
+        self.state = state;
+        end
+    }
+}
+
+/// What workers need to type the records of a chunk like the first one was.
+struct ChunkTyper {
+    dialect: CsvDialect,
+    headers: Vec<String>,
+    columns: TypedColumns,
+    /// Fields of the first row, which every row has unless `--flexible`
+    width: Option<usize>,
+}
+
+impl ChunkTyper {
+    fn type_chunk(&self, chunk: &Chunk) -> Vec<Result<Record>> {
+        let reader = self.dialect.reader_builder().has_headers(false).flexible(true).from_reader(&chunk.data[..]);
+        let records = reader.into_byte_records().map(|result| {
+            let mut record = result.with_context(|| format!("Invalid CSV in the lines from {}", chunk.first_line))?;
+            // lines of the whole input, not of the chunk
+            if let Some(mut position) = record.position().cloned() {
+                position.set_line(chunk.first_line + position.line() - 1);
+                record.set_position(Some(position));
+            }
+            string_record(record, self.width)
+        });
+        self.type_records(records)
+    }
+
+    fn type_records(&self, records: impl Iterator<Item = Result<StringRecord>>) -> Vec<Result<Record>> {
+        let mut headers = self.headers.clone();
+        records.map(|result| type_record(&mut headers, &self.columns, result?)).collect()
+    }
+}
+
+/// Hands chunks to the workers, keeping up to `window` of them in flight, and puts
+/// their records back in input order.
+struct OrderedRecords {
+    chunker: Chunker,
+    chunk_size: usize,
+    work: mpsc::Sender<Chunk>,
+    results: mpsc::Receiver<(usize, Vec<Result<Record>>)>,
+    pending: BTreeMap<usize, Vec<Result<Record>>>,
+    current: std::vec::IntoIter<Result<Record>>,
+    next: usize,
+    sent: usize,
+    window: usize,
+}
+
+impl OrderedRecords {
+    /// End the records with `error`, after those read so far.
+    fn fail(&mut self, error: anyhow::Error) {
+        self.pending.insert(self.sent, vec![Err(error)]);
+        self.sent += 1;
+        self.chunker.eof = true;
+        self.chunker.buf.clear();
+    }
+}
+
+impl Iterator for OrderedRecords {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.current.next() {
+                return Some(record);
+            }
+            while self.sent - self.next < self.window && !self.chunker.is_done() {
+                match self.chunker.next_chunk(self.chunk_size, 1) {
+                    Ok(Some(chunk)) => match self.work.send(chunk) {
+                        Ok(()) => self.sent += 1,
+                        Err(_) => self.fail(anyhow::anyhow!("A CSV worker thread stopped")),
+                    },
+                    Ok(None) => break,
+                    Err(e) => self.fail(e),
+                }
+            }
+            if self.next == self.sent {
+                return None;
+            }
+            let records = loop {
+                if let Some(records) = self.pending.remove(&self.next) {
+                    break records;
+                }
+                match self.results.recv() {
+                    Ok((index, records)) => {
+                        self.pending.insert(index, records);
+                    }
+                    Err(_) => {
+                        self.next = self.sent;
+                        return Some(Err(anyhow::anyhow!("A CSV worker thread stopped")));
+                    }
+                }
+            };
+            self.next += 1;
+            self.current = records.into_iter();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::csv_convert::csv_records;
+    use std::io::Cursor;
+
+    fn read(data: &str, dialect: &CsvDialect, jobs: usize, chunk_size: usize) -> Result<(Vec<String>, Vec<Result<Record>>)> {
+        let types = CsvTypes { sample_rows: 2, ..Default::default() };
+        let stream = match jobs {
+            1 => csv_records(Cursor::new(data.to_string()), dialect, &types)?,
+            _ => chunked_csv_records(Box::new(Cursor::new(data.to_string())), dialect, &types, jobs, chunk_size)?,
+        };
+        Ok((stream.headers, stream.records.collect()))
+    }
+
+    fn rows(data: &str, dialect: &CsvDialect, jobs: usize, chunk_size: usize) -> Result<(Vec<String>, Vec<Record>)> {
+        let (headers, records) = read(data, dialect, jobs, chunk_size)?;
+        Ok((headers, records.into_iter().collect::<Result<_>>()?))
+    }
+
+    #[test]
+    fn test_scanner() {
+        let dialect = CsvDialect { comment: Some(b'#'), ..Default::default() };
+        let mut scanner = Scanner::new(&dialect);
+        let ends: Vec<usize> = "a,\"b\nc\"\"\n\"\n# x,\"y\n\nd\r\n"
+            .bytes()
+            .enumerate()
+            .filter_map(|(i, b)| scanner.feed(b).then_some(i))
+            .collect();
+        assert_eq!(ends, vec![10, 21]);
+    }
+
+    #[test]
+    fn test_parallel_matches_serial() -> Result<()> {
+        let mut data = String::from("# roster\nName,Note,Kit Number\n");
+        for i in 0..300 {
+            data.push_str(&format!("\"Player, {}\",\"says \"\"hi\"\"\ntwice\",{}\n", i, i % 99));
+            if i % 50 == 0 {
+                data.push_str("# a comment\n\n");
+            }
+        }
+        let dialect = CsvDialect { comment: Some(b'#'), ..Default::default() };
+        let serial = rows(&data, &dialect, 1, 0)?;
+        assert_eq!(serial.1.len(), 300);
+        for (jobs, chunk_size) in [(2, 1), (3, 64), (4, 1000), (2, 1 << 20)] {
+            assert_eq!(rows(&data, &dialect, jobs, chunk_size)?, serial);
+        }
+
+        let dialect = CsvDialect { header: false, escape: Some(b'\\'), ..Default::default() };
+        let data = "a,\"x\\\"\n,y\"\n".repeat(20);
+        assert_eq!(rows(&data, &dialect, 3, 8)?, rows(&data, &dialect, 1, 0)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_errors() -> Result<()> {
+        let mut data = String::from("Name,Kit Number\n");
+        for i in 0..20 {
+            data.push_str(&format!("Player {},{}\n", i, i));
+        }
+        data.push_str("Buffon,77,GK\n");
+        let dialect = CsvDialect::default();
+        let (_, records) = read(&data, &dialect, 3, 16)?;
+        assert_eq!(records.len(), 21);
+        assert!(records[..20].iter().all(|r| r.is_ok()));
+        let err = records[20].as_ref().unwrap_err().to_string();
+        assert!(err.contains("line 22"), "{}", err);
+
+        let dialect = CsvDialect { flexible: true, ..Default::default() };
+        let (_, records) = rows(&data, &dialect, 3, 16)?;
+        assert_eq!(records[20]["col_3"], "GK");
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+const BAR_WIDTH: usize = 30;
+const REDRAW: Duration = Duration::from_millis(100);
+
+/// A progress bar on stderr: how much of the input file was read, rows written and rows/sec.
+/// Without a file size to go by, as for stdin, only the counts are shown.
+pub struct Progress {
+    total: Option<u64>,
+    read: Arc<AtomicU64>,
+    rows: u64,
+    start: Instant,
+    drawn: Instant,
+}
+
+/// Counts the bytes read through it into a shared counter.
+pub struct CountingReader<R> {
+    inner: R,
+    read: Arc<AtomicU64>,
+}
+
+impl Progress {
+    pub fn new(input: &str) -> Self {
+        let total = match input {
+            "-" => None,
+            _ => fs::metadata(input).ok().map(|m| m.len()).filter(|len| *len > 0),
+        };
+        let now = Instant::now();
+        Self { total, read: Arc::new(AtomicU64::new(0)), rows: 0, start: now, drawn: now }
+    }
+
+    /// Wrap the raw input, before decompression, so the bar follows the file size.
+    pub fn counting<R: Read>(&self, inner: R) -> CountingReader<R> {
+        CountingReader { inner, read: self.read.clone() }
+    }
+
+    /// Count a written row, redrawing now and then.
+    pub fn tick(&mut self) {
+        self.rows += 1;
+        if self.rows.is_multiple_of(256) && self.drawn.elapsed() >= REDRAW {
+            self.draw();
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.draw();
+        eprintln!();
+    }
+
+    fn draw(&mut self) {
+        self.drawn = Instant::now();
+        let line = render(self.rows, self.read.load(Ordering::Relaxed), self.total, self.start.elapsed());
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r{}", line);
+        let _ = stderr.flush();
+    }
+}
+
+fn render(rows: u64, read: u64, total: Option<u64>, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { (rows as f64 / secs) as u64 } else { 0 };
+    let counts = format!("{} rows  {:>9} rows/s  {:.1}s", rows, rate, secs);
+    match total {
+        Some(total) => {
+            let done = read.min(total) as f64 / total as f64;
+            let filled = (done * BAR_WIDTH as f64) as usize;
+            let bar = match filled {
+                BAR_WIDTH => "=".repeat(BAR_WIDTH),
+                _ => format!("{}>{}", "=".repeat(filled), " ".repeat(BAR_WIDTH - filled - 1)),
+            };
+            format!("[{}] {:>3}%  {}", bar, (done * 100.0) as u64, counts)
+        }
+        None => counts,
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let line = render(5000, 50, Some(200), Duration::from_secs(2));
+        assert_eq!(line, format!("[=======>{}]  25%  5000 rows       2500 rows/s  2.0s", " ".repeat(22)));
+        assert!(render(10, 400, Some(200), Duration::from_secs(1)).starts_with(&format!("[{}] 100%", "=".repeat(30))));
+        assert_eq!(render(0, 0, None, Duration::ZERO), "0 rows          0 rows/s  0.0s");
+    }
+
+    #[test]
+    fn test_counting_reader() -> io::Result<()> {
+        let progress = Progress::new("-");
+        let mut reader = progress.counting(&b"Name,Kit Number\nBuffon,77\n"[..]);
+        io::copy(&mut reader, &mut io::sink())?;
+        assert_eq!(progress.read.load(Ordering::Relaxed), 26);
+        Ok(())
+    }
+}
@@ -0,0 +1,1197 @@
+use std::{cmp::Ordering, collections::{HashMap, HashSet}, fmt, path::Path, str::FromStr};
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde_json::{Number, Value};
+
+use crate::cli::{AggFunc, CsvDialect, CsvSource, CsvTypes, OutputOpts, SortMode};
+use super::{
+    csv_convert::{source_records, Record, RecordStream},
+    csv_nested::reshape_records,
+    csv_filter::{as_number, compare_values},
+    csv_infer::parse_leading_date,
+    csv_writer::{cell_text, write_stream},
+};
+
+const KEYWORDS: &[&str] = &[
+    "select", "distinct", "from", "as", "join", "inner", "left", "outer", "on", "where", "group", "by",
+    "having", "order", "asc", "desc", "limit", "offset", "and", "or", "not", "is", "null", "like", "in",
+];
+
+/// A parsed `SELECT` statement, tables are CSV (or any supported input) file paths.
+#[derive(Debug, Clone)]
+pub struct Query {
+    distinct: bool,
+    items: Vec<SelectItem>,
+    from: TableRef,
+    joins: Vec<Join>,
+    filter: Option<SqlExpr>,
+    group_by: Vec<SqlExpr>,
+    having: Option<SqlExpr>,
+    order_by: Vec<(SqlExpr, bool)>,
+    limit: Option<usize>,
+    offset: usize,
+}
+
+#[derive(Debug, Clone)]
+enum SelectItem {
+    Wildcard(Option<String>),
+    Expr(SqlExpr, Option<String>),
+}
+
+#[derive(Debug, Clone)]
+struct TableRef {
+    path: String,
+    alias: String,
+}
+
+#[derive(Debug, Clone)]
+struct Join {
+    table: TableRef,
+    left: bool,
+    on: SqlExpr,
+}
+
+#[derive(Debug, Clone)]
+enum SqlExpr {
+    Column(Option<String>, String),
+    // a column bound to its position in the joined rows
+    Index(usize),
+    Literal(Value),
+    Neg(Box<SqlExpr>),
+    Not(Box<SqlExpr>),
+    Binary(Box<SqlExpr>, BinOp, Box<SqlExpr>),
+    IsNull(Box<SqlExpr>, bool),
+    Like(Box<SqlExpr>, Regex, bool),
+    In(Box<SqlExpr>, Vec<SqlExpr>, bool),
+    Func(String, Vec<SqlExpr>),
+    Agg(AggFunc, Option<Box<SqlExpr>>, bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    And,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Rows of one or more joined tables, columns are `(table alias, column name)`.
+struct Relation {
+    columns: Vec<(String, String)>,
+    rows: Vec<Vec<Value>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Ident(String),
+    Str(String),
+    Sym(&'static str),
+}
+
+pub fn process_csv_query(sql: &str, dialect: &CsvDialect, types: &CsvTypes, output: &str, out: &OutputOpts) -> Result<()> {
+    let query: Query = sql.parse()?;
+    let (headers, rows) = query.execute(|path| {
+        let source = CsvSource {
+            input: path.to_string(),
+            from: None,
+            dialect: dialect.clone(),
+            types: types.clone(),
+        };
+        let stream = source_records(&source, "rows").with_context(|| format!("Cannot read table {:?}", path))?;
+        Ok((stream.headers, stream.records))
+    })?;
+
+    let records = rows.into_iter().map(|row| Ok(headers.iter().cloned().zip(row).collect::<Record>()));
+    let stream = reshape_records(RecordStream { headers: headers.clone(), dates: Vec::new(), records: Box::new(records) }, out)?;
+    write_stream(stream, output, out)
+}
+
+type TableRows = (Vec<String>, Box<dyn Iterator<Item = Result<Record>>>);
+
+impl Query {
+    /// Run the query, `load` opens a table by path. Returns the output column names and rows.
+    fn execute(&self, load: impl Fn(&str) -> Result<TableRows>) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+        let open = |table: &TableRef| -> Result<Relation> {
+            let (headers, records) = load(&table.path)?;
+            let rows = records
+                .map(|record| {
+                    let mut record = record?;
+                    Ok(headers.iter().map(|h| record.remove(h).unwrap_or(Value::Null)).collect())
+                })
+                .collect::<Result<_>>()?;
+            let columns = headers.into_iter().map(|h| (table.alias.clone(), h)).collect();
+            Ok(Relation { columns, rows })
+        };
+        let mut rel = open(&self.from)?;
+        for join in &self.joins {
+            rel = rel.join(open(&join.table)?, &join.on, join.left)?;
+        }
+
+        // expand `*` and name the output columns
+        let mut names = Vec::new();
+        let mut exprs = Vec::new();
+        for item in &self.items {
+            match item {
+                SelectItem::Wildcard(table) => {
+                    for (i, (alias, name)) in rel.columns.iter().enumerate() {
+                        if table.as_ref().is_some_and(|t| t != alias) {
+                            continue;
+                        }
+                        let ambiguous = rel.columns.iter().filter(|(_, n)| n == name).count() > 1;
+                        names.push(if ambiguous { format!("{}.{}", alias, name) } else { name.clone() });
+                        exprs.push(SqlExpr::Index(i));
+                    }
+                }
+                SelectItem::Expr(expr, alias) => {
+                    let bound = expr.bind(&rel)?;
+                    names.push(match (alias, &bound) {
+                        (Some(alias), _) => alias.clone(),
+                        (None, SqlExpr::Index(i)) => rel.columns[*i].1.clone(),
+                        (None, _) => expr.to_string(),
+                    });
+                    exprs.push(bound);
+                }
+            }
+        }
+        if exprs.is_empty() {
+            anyhow::bail!("No columns selected");
+        }
+
+        let filter = self.filter.as_ref().map(|e| rel.bind_row_expr(e, "WHERE")).transpose()?;
+        let rows: Vec<&Vec<Value>> = match &filter {
+            Some(filter) => rel
+                .rows
+                .iter()
+                .filter(|row| eval(filter, &[row.as_slice()]).map(|v| truthy(&v)).unwrap_or(false))
+                .collect(),
+            None => rel.rows.iter().collect(),
+        };
+
+        let group_by = self
+            .group_by
+            .iter()
+            .map(|e| match ordinal(e) {
+                Some(i) => exprs.get(i).cloned().ok_or_else(|| anyhow::anyhow!("GROUP BY position {} is out of range", i + 1)),
+                None => rel.bind_row_expr(e, "GROUP BY"),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let having = self.having.as_ref().map(|e| e.bind(&rel)).transpose()?;
+        let grouped = !group_by.is_empty() || having.is_some() || exprs.iter().any(SqlExpr::has_agg);
+
+        // ORDER BY refers to an output column by position or name, or is an expression
+        let order_by = self
+            .order_by
+            .iter()
+            .map(|(e, desc)| {
+                let key = match (ordinal(e), e) {
+                    (Some(i), _) if i < names.len() => OrderKey::Output(i),
+                    (Some(i), _) => anyhow::bail!("ORDER BY position {} is out of range", i + 1),
+                    (None, SqlExpr::Column(None, name)) if names.contains(name) => {
+                        OrderKey::Output(names.iter().position(|n| n == name).unwrap_or_default())
+                    }
+                    (None, e) => OrderKey::Expr(e.bind(&rel)?),
+                };
+                Ok((key, *desc))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let groups: Vec<Vec<&[Value]>> = if grouped {
+            let mut index: HashMap<String, usize> = HashMap::new();
+            let mut groups: Vec<Vec<&[Value]>> = Vec::new();
+            for row in rows {
+                let key = group_by.iter().map(|e| eval(e, &[row])).collect::<Result<Vec<_>>>()?;
+                let key = serde_json::to_string(&key)?;
+                let i = *index.entry(key).or_insert_with(|| {
+                    groups.push(Vec::new());
+                    groups.len() - 1
+                });
+                groups[i].push(row);
+            }
+            // aggregates without GROUP BY make one group, even over no rows
+            if groups.is_empty() && group_by.is_empty() {
+                groups.push(Vec::new());
+            }
+            groups
+        } else {
+            rows.into_iter().map(|row| vec![row.as_slice()]).collect()
+        };
+
+        let mut output = Vec::with_capacity(groups.len());
+        let mut seen = HashSet::new();
+        for group in &groups {
+            if let Some(having) = &having {
+                if !truthy(&eval(having, group)?) {
+                    continue;
+                }
+            }
+            let row = exprs.iter().map(|e| eval(e, group)).collect::<Result<Vec<_>>>()?;
+            if self.distinct && !seen.insert(serde_json::to_string(&row)?) {
+                continue;
+            }
+            let keys = order_by
+                .iter()
+                .map(|(key, _)| match key {
+                    OrderKey::Output(i) => Ok(row[*i].clone()),
+                    OrderKey::Expr(e) => eval(e, group),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            output.push((keys, row));
+        }
+
+        if !order_by.is_empty() {
+            output.sort_by(|(a, _), (b, _)| {
+                a.iter()
+                    .zip(b)
+                    .zip(&order_by)
+                    .map(|((a, b), (_, desc))| match (a.is_null(), b.is_null()) {
+                        (true, true) => Ordering::Equal,
+                        (true, false) => Ordering::Greater,
+                        (false, true) => Ordering::Less,
+                        _ if *desc => compare_values(a, b, SortMode::Auto).reverse(),
+                        _ => compare_values(a, b, SortMode::Auto),
+                    })
+                    .find(|o| o.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+        let rows = output
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .map(|(_, row)| row)
+            .collect();
+        Ok((unique_names(names), rows))
+    }
+}
+
+enum OrderKey {
+    Output(usize),
+    Expr(SqlExpr),
+}
+
+/// `GROUP BY 1` / `ORDER BY 2` refer to select items by their 1-based position.
+fn ordinal(expr: &SqlExpr) -> Option<usize> {
+    match expr {
+        SqlExpr::Literal(Value::Number(n)) => n.as_u64().filter(|n| *n > 0).map(|n| n as usize - 1),
+        _ => None,
+    }
+}
+
+/// Records are keyed by column name, so repeated names get a `_2`, `_3`... suffix.
+fn unique_names(names: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    names
+        .into_iter()
+        .map(|name| {
+            let mut unique = name.clone();
+            let mut n = 1;
+            while !seen.insert(unique.clone()) {
+                n += 1;
+                unique = format!("{}_{}", name, n);
+            }
+            unique
+        })
+        .collect()
+}
+
+impl Relation {
+    fn resolve(&self, table: Option<&str>, name: &str) -> Result<usize> {
+        let find = |table: Option<&str>, name: &str| -> Vec<usize> {
+            self.columns
+                .iter()
+                .enumerate()
+                .filter(|(_, (alias, column))| column == name && table.is_none_or(|t| t == alias))
+                .map(|(i, _)| i)
+                .collect()
+        };
+        let mut found = find(table, name);
+        // `t.column` written without quotes
+        if found.is_empty() && table.is_none() {
+            if let Some((table, name)) = name.split_once('.') {
+                found = find(Some(table), name);
+            }
+        }
+        match found.as_slice() {
+            [i] => Ok(*i),
+            [] => Err(anyhow::anyhow!("Unknown column {:?}", name)),
+            _ => Err(anyhow::anyhow!("Ambiguous column {:?}, qualify it with a table name", name)),
+        }
+    }
+
+    fn bind_row_expr(&self, expr: &SqlExpr, clause: &str) -> Result<SqlExpr> {
+        if expr.has_agg() {
+            anyhow::bail!("Aggregate functions are not allowed in {}", clause);
+        }
+        expr.bind(self)
+    }
+
+    fn join(self, right: Relation, on: &SqlExpr, outer: bool) -> Result<Relation> {
+        let width = self.columns.len();
+        let right_width = right.columns.len();
+        let mut columns = self.columns;
+        columns.extend(right.columns);
+        let combined = Relation { columns, rows: Vec::new() };
+        let on = combined.bind_row_expr(on, "ON")?;
+
+        let mut rows = Vec::new();
+        let mut emit = |left: &Vec<Value>, matches: &mut dyn Iterator<Item = &Vec<Value>>| {
+            let mut matched = false;
+            for r in matches {
+                rows.push(left.iter().chain(r).cloned().collect::<Vec<_>>());
+                matched = true;
+            }
+            if !matched && outer {
+                rows.push(left.iter().cloned().chain(std::iter::repeat_n(Value::Null, right_width)).collect());
+            }
+        };
+
+        match on {
+            // equality between a column of each side: hash join
+            SqlExpr::Binary(ref a, BinOp::Eq, ref b) if equi_columns(a, b, width).is_some() => {
+                let (l, r) = equi_columns(a, b, width).unwrap_or_default();
+                let mut index: HashMap<String, Vec<&Vec<Value>>> = HashMap::new();
+                for row in &right.rows {
+                    if let Some(key) = join_key(&row[r - width]) {
+                        index.entry(key).or_default().push(row);
+                    }
+                }
+                for left in &self.rows {
+                    let matches = join_key(&left[l]).and_then(|k| index.get(&k));
+                    emit(left, &mut matches.into_iter().flatten().copied());
+                }
+            }
+            on => {
+                for left in &self.rows {
+                    let mut matches = right.rows.iter().filter(|r| {
+                        let row: Vec<Value> = left.iter().chain(r.iter()).cloned().collect();
+                        eval(&on, &[&row]).map(|v| truthy(&v)).unwrap_or(false)
+                    });
+                    emit(left, &mut matches);
+                }
+            }
+        }
+        Ok(Relation { columns: combined.columns, rows })
+    }
+}
+
+fn equi_columns(a: &SqlExpr, b: &SqlExpr, width: usize) -> Option<(usize, usize)> {
+    match (a, b) {
+        (SqlExpr::Index(a), SqlExpr::Index(b)) if *a < width && *b >= width => Some((*a, *b)),
+        (SqlExpr::Index(a), SqlExpr::Index(b)) if *b < width && *a >= width => Some((*b, *a)),
+        _ => None,
+    }
+}
+
+/// Nulls never join, numbers join on their value whatever their representation.
+fn join_key(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Number(n) => n.as_f64().map(|f| f.to_string()),
+        _ => Some(cell_text(value).into_owned()),
+    }
+}
+
+impl SqlExpr {
+    fn has_agg(&self) -> bool {
+        match self {
+            SqlExpr::Agg(..) => true,
+            SqlExpr::Neg(e) | SqlExpr::Not(e) | SqlExpr::IsNull(e, _) | SqlExpr::Like(e, _, _) => e.has_agg(),
+            SqlExpr::Binary(a, _, b) => a.has_agg() || b.has_agg(),
+            SqlExpr::In(e, list, _) => e.has_agg() || list.iter().any(SqlExpr::has_agg),
+            SqlExpr::Func(_, args) => args.iter().any(SqlExpr::has_agg),
+            SqlExpr::Column(..) | SqlExpr::Index(_) | SqlExpr::Literal(_) => false,
+        }
+    }
+
+    /// Replace column names by their position in `rel`.
+    fn bind(&self, rel: &Relation) -> Result<SqlExpr> {
+        let bind = |e: &SqlExpr| e.bind(rel).map(Box::new);
+        let expr = match self {
+            SqlExpr::Column(table, name) => SqlExpr::Index(rel.resolve(table.as_deref(), name)?),
+            SqlExpr::Index(_) | SqlExpr::Literal(_) => self.clone(),
+            SqlExpr::Neg(e) => SqlExpr::Neg(bind(e)?),
+            SqlExpr::Not(e) => SqlExpr::Not(bind(e)?),
+            SqlExpr::Binary(a, op, b) => SqlExpr::Binary(bind(a)?, *op, bind(b)?),
+            SqlExpr::IsNull(e, negated) => SqlExpr::IsNull(bind(e)?, *negated),
+            SqlExpr::Like(e, re, negated) => SqlExpr::Like(bind(e)?, re.clone(), *negated),
+            SqlExpr::In(e, list, negated) => SqlExpr::In(
+                bind(e)?,
+                list.iter().map(|e| e.bind(rel)).collect::<Result<_>>()?,
+                *negated,
+            ),
+            SqlExpr::Func(name, args) => SqlExpr::Func(name.clone(), args.iter().map(|e| e.bind(rel)).collect::<Result<_>>()?),
+            SqlExpr::Agg(agg, arg, distinct) => SqlExpr::Agg(*agg, arg.as_deref().map(bind).transpose()?, *distinct),
+        };
+        Ok(expr)
+    }
+}
+
+/// Evaluate a bound expression over a group of rows. Outside aggregates the
+/// first row of the group is used, a plain row is a group of one.
+fn eval(expr: &SqlExpr, rows: &[&[Value]]) -> Result<Value> {
+    let value = match expr {
+        SqlExpr::Index(i) => rows.first().and_then(|row| row.get(*i)).cloned().unwrap_or(Value::Null),
+        SqlExpr::Column(_, name) => anyhow::bail!("Column {:?} is not bound", name),
+        SqlExpr::Literal(value) => value.clone(),
+        SqlExpr::Neg(e) => arithmetic(&Value::from(0), BinOp::Sub, &eval(e, rows)?),
+        SqlExpr::Not(e) => match eval(e, rows)? {
+            Value::Null => Value::Null,
+            v => Value::Bool(!truthy(&v)),
+        },
+        SqlExpr::Binary(a, BinOp::And, b) => Value::Bool(truthy(&eval(a, rows)?) && truthy(&eval(b, rows)?)),
+        SqlExpr::Binary(a, BinOp::Or, b) => Value::Bool(truthy(&eval(a, rows)?) || truthy(&eval(b, rows)?)),
+        SqlExpr::Binary(a, op, b) => {
+            let (a, b) = (eval(a, rows)?, eval(b, rows)?);
+            match op {
+                BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div => arithmetic(&a, *op, &b),
+                _ => match sql_compare(&a, &b) {
+                    None => Value::Null,
+                    Some(ordering) => Value::Bool(match op {
+                        BinOp::Eq => ordering.is_eq(),
+                        BinOp::Ne => ordering.is_ne(),
+                        BinOp::Lt => ordering.is_lt(),
+                        BinOp::Le => ordering.is_le(),
+                        BinOp::Gt => ordering.is_gt(),
+                        _ => ordering.is_ge(),
+                    }),
+                },
+            }
+        }
+        SqlExpr::IsNull(e, negated) => Value::Bool(eval(e, rows)?.is_null() != *negated),
+        SqlExpr::Like(e, re, negated) => match eval(e, rows)? {
+            Value::Null => Value::Null,
+            v => Value::Bool(re.is_match(&cell_text(&v)) != *negated),
+        },
+        SqlExpr::In(e, list, negated) => {
+            let v = eval(e, rows)?;
+            let mut found = false;
+            for item in list {
+                found |= sql_compare(&v, &eval(item, rows)?) == Some(Ordering::Equal);
+            }
+            Value::Bool(found != *negated)
+        }
+        SqlExpr::Func(name, args) => {
+            let args = args.iter().map(|e| eval(e, rows)).collect::<Result<Vec<_>>>()?;
+            scalar(name, &args)?
+        }
+        SqlExpr::Agg(agg, arg, distinct) => {
+            let mut values = Vec::with_capacity(rows.len());
+            for row in rows {
+                match arg {
+                    Some(arg) => values.push(eval(arg, &[row])?),
+                    None => values.push(Value::Bool(true)),
+                }
+            }
+            values.retain(|v| !v.is_null());
+            if *distinct {
+                let mut seen = HashSet::new();
+                values.retain(|v| seen.insert(v.to_string()));
+            }
+            aggregate(*agg, values)
+        }
+    };
+    Ok(value)
+}
+
+/// Fold the non-null values of a group, sums of integers stay integers.
+pub fn aggregate(agg: AggFunc, values: Vec<Value>) -> Value {
+    match agg {
+        AggFunc::Count => Value::from(values.len()),
+        AggFunc::Sum | AggFunc::Avg => {
+            let numbers: Vec<&Value> = values.iter().filter(|v| as_number(v).is_some()).collect();
+            if numbers.is_empty() {
+                return Value::Null;
+            }
+            let sum: f64 = numbers.iter().filter_map(|v| as_number(v)).sum();
+            match agg {
+                AggFunc::Avg => number(sum / numbers.len() as f64),
+                _ if numbers.iter().all(|v| v.is_i64()) => Value::from(numbers.iter().filter_map(|v| v.as_i64()).sum::<i64>()),
+                _ => number(sum),
+            }
+        }
+        AggFunc::Min => values.into_iter().min_by(compare_extremes).unwrap_or(Value::Null),
+        AggFunc::Max => values.into_iter().max_by(compare_extremes).unwrap_or(Value::Null),
+    }
+}
+
+/// Dates written as text compare as dates, so min/max of a date column is the earliest/latest.
+fn compare_extremes(a: &Value, b: &Value) -> Ordering {
+    let date = |v: &Value| v.as_str().and_then(parse_leading_date);
+    match (date(a), date(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => compare_values(a, b, SortMode::Auto),
+    }
+}
+
+fn scalar(name: &str, args: &[Value]) -> Result<Value> {
+    let arg = || args.first().cloned().unwrap_or(Value::Null);
+    let value = match name {
+        "coalesce" => args.iter().find(|v| !v.is_null()).cloned().unwrap_or(Value::Null),
+        _ if arg().is_null() => Value::Null,
+        "lower" => Value::String(cell_text(&arg()).to_lowercase()),
+        "upper" => Value::String(cell_text(&arg()).to_uppercase()),
+        "length" => Value::from(cell_text(&arg()).chars().count()),
+        "abs" => as_number(&arg()).map_or(Value::Null, |n| number(n.abs())),
+        "round" => {
+            let digits = args.get(1).and_then(as_number).unwrap_or(0.0) as i32;
+            let scale = 10f64.powi(digits);
+            as_number(&arg()).map_or(Value::Null, |n| number((n * scale).round() / scale))
+        }
+        _ => anyhow::bail!("Unknown function {}", name),
+    };
+    Ok(value)
+}
+
+/// A float that is a whole number comes out as an integer.
+fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+        Value::from(n as i64)
+    } else {
+        Number::from_f64(n).map_or(Value::Null, Value::Number)
+    }
+}
+
+fn arithmetic(a: &Value, op: BinOp, b: &Value) -> Value {
+    if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+        let ret = match op {
+            BinOp::Add => a.checked_add(b),
+            BinOp::Sub => a.checked_sub(b),
+            BinOp::Mul => a.checked_mul(b),
+            _ => None,
+        };
+        if let Some(ret) = ret {
+            return Value::from(ret);
+        }
+    }
+    match (as_number(a), as_number(b)) {
+        (Some(a), Some(b)) => match op {
+            BinOp::Add => number(a + b),
+            BinOp::Sub => number(a - b),
+            BinOp::Mul => number(a * b),
+            _ if b == 0.0 => Value::Null,
+            _ => number(a / b),
+        },
+        _ => Value::Null,
+    }
+}
+
+/// `None` when either side is null. Numbers compare by value, also against numeric strings.
+fn sql_compare(a: &Value, b: &Value) -> Option<Ordering> {
+    if a.is_null() || b.is_null() {
+        return None;
+    }
+    if a.is_number() || b.is_number() {
+        if let (Some(a), Some(b)) = (as_number(a), as_number(b)) {
+            return Some(a.total_cmp(&b));
+        }
+    }
+    Some(compare_values(a, b, SortMode::Lexical))
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        _ => true,
+    }
+}
+
+fn like_regex(pattern: &str) -> Result<Regex> {
+    let mut re = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '%' => re.push_str(".*"),
+            '_' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Ok(Regex::new(&re)?)
+}
+
+impl fmt::Display for SqlExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SqlExpr::Column(_, name) => write!(f, "{}", name),
+            SqlExpr::Index(i) => write!(f, "#{}", i),
+            SqlExpr::Literal(v) => write!(f, "{}", v),
+            SqlExpr::Neg(e) => write!(f, "-{}", e),
+            SqlExpr::Not(e) => write!(f, "not {}", e),
+            SqlExpr::Binary(a, op, b) => write!(f, "{} {} {}", a, op, b),
+            SqlExpr::IsNull(e, negated) => write!(f, "{} is {}null", e, if *negated { "not " } else { "" }),
+            SqlExpr::Like(e, re, _) => write!(f, "{} like {}", e, re),
+            SqlExpr::In(e, list, _) => {
+                let list: Vec<_> = list.iter().map(|e| e.to_string()).collect();
+                write!(f, "{} in ({})", e, list.join(", "))
+            }
+            SqlExpr::Func(name, args) => {
+                let args: Vec<_> = args.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+            SqlExpr::Agg(agg, arg, distinct) => {
+                let distinct = if *distinct { "distinct " } else { "" };
+                match arg {
+                    Some(arg) => write!(f, "{}({}{})", agg, distinct, arg),
+                    None => write!(f, "{}(*)", agg),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            BinOp::And => "and",
+            BinOp::Or => "or",
+            BinOp::Eq => "=",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl FromStr for Query {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = SqlParser { tokens: tokenize(s)?, pos: 0 };
+        let query = parser.query()?;
+        parser.eat_sym(";");
+        match parser.peek() {
+            None => Ok(query),
+            Some(token) => Err(anyhow::anyhow!("Unexpected {:?} at the end of the query", token)),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    const SYMBOLS: &[&str] = &["<=", ">=", "<>", "!=", "=", "<", ">", ",", "(", ")", "*", "+", "-", "/", ";"];
+    let mut tokens = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if matches!(c, '\'' | '"' | '`') {
+            let mut text = String::new();
+            let mut chars = rest[1..].char_indices().peekable();
+            let end = loop {
+                match chars.next() {
+                    // a doubled quote stands for the quote itself
+                    Some((_, q)) if q == c && chars.peek().map(|(_, n)| *n) == Some(c) => {
+                        chars.next();
+                        text.push(c);
+                    }
+                    Some((i, q)) if q == c => break i + 2,
+                    Some((_, q)) => text.push(q),
+                    None => anyhow::bail!("Unterminated {} in query", c),
+                }
+            };
+            tokens.push(if c == '\'' { Token::Str(text) } else { Token::Ident(text) });
+            rest = &rest[end..];
+        } else if let Some(sym) = SYMBOLS.iter().find(|sym| rest.starts_with(**sym)) {
+            tokens.push(Token::Sym(sym));
+            rest = &rest[sym.len()..];
+        } else if c.is_alphanumeric() || c == '_' || c == '.' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            anyhow::bail!("Unexpected character {:?} in query", c);
+        }
+    }
+    Ok(tokens)
+}
+
+struct SqlParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl SqlParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn is_kw(&self, offset: usize, kw: &str) -> bool {
+        matches!(self.tokens.get(self.pos + offset), Some(Token::Word(w)) if w.eq_ignore_ascii_case(kw))
+    }
+
+    fn eat_kw(&mut self, kw: &str) -> bool {
+        let found = self.is_kw(0, kw);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_kw(&mut self, kw: &str) -> Result<()> {
+        match self.eat_kw(kw) {
+            true => Ok(()),
+            false => Err(anyhow::anyhow!("Expected {} but found {:?}", kw.to_uppercase(), self.peek())),
+        }
+    }
+
+    fn eat_sym(&mut self, sym: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Sym(s)) if *s == sym);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_sym(&mut self, sym: &str) -> Result<()> {
+        match self.eat_sym(sym) {
+            true => Ok(()),
+            false => Err(anyhow::anyhow!("Expected {:?} but found {:?}", sym, self.peek())),
+        }
+    }
+
+    /// An alias: `AS name`, or a bare name that isn't a keyword.
+    fn alias(&mut self) -> Result<Option<String>> {
+        let explicit = self.eat_kw("as");
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(Some(name))
+            }
+            Some(Token::Word(name)) if !KEYWORDS.contains(&name.to_ascii_lowercase().as_str()) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(Some(name))
+            }
+            _ if explicit => Err(anyhow::anyhow!("Expected a name after AS")),
+            _ => Ok(None),
+        }
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        match self.next() {
+            Some(Token::Word(w)) => w.parse().with_context(|| format!("Expected a number, found {:?}", w)),
+            other => Err(anyhow::anyhow!("Expected a number, found {:?}", other)),
+        }
+    }
+
+    fn query(&mut self) -> Result<Query> {
+        self.expect_kw("select")?;
+        let distinct = self.eat_kw("distinct");
+        let mut items = Vec::new();
+        loop {
+            items.push(self.select_item()?);
+            if !self.eat_sym(",") {
+                break;
+            }
+        }
+        self.expect_kw("from")?;
+        let from = self.table()?;
+
+        let mut joins = Vec::new();
+        loop {
+            let left = if self.eat_kw("left") {
+                self.eat_kw("outer");
+                true
+            } else {
+                self.eat_kw("inner");
+                false
+            };
+            if !self.eat_kw("join") {
+                if left || self.is_kw(0, "join") {
+                    anyhow::bail!("Expected JOIN");
+                }
+                break;
+            }
+            let table = self.table()?;
+            self.expect_kw("on")?;
+            joins.push(Join { table, left, on: self.expr()? });
+        }
+
+        let filter = if self.eat_kw("where") { Some(self.expr()?) } else { None };
+        let mut group_by = Vec::new();
+        if self.eat_kw("group") {
+            self.expect_kw("by")?;
+            loop {
+                group_by.push(self.expr()?);
+                if !self.eat_sym(",") {
+                    break;
+                }
+            }
+        }
+        let having = if self.eat_kw("having") { Some(self.expr()?) } else { None };
+        let mut order_by = Vec::new();
+        if self.eat_kw("order") {
+            self.expect_kw("by")?;
+            loop {
+                let expr = self.expr()?;
+                let desc = self.eat_kw("desc");
+                if !desc {
+                    self.eat_kw("asc");
+                }
+                order_by.push((expr, desc));
+                if !self.eat_sym(",") {
+                    break;
+                }
+            }
+        }
+        let limit = if self.eat_kw("limit") { Some(self.usize()?) } else { None };
+        let offset = if self.eat_kw("offset") { self.usize()? } else { 0 };
+
+        Ok(Query { distinct, items, from, joins, filter, group_by, having, order_by, limit, offset })
+    }
+
+    fn select_item(&mut self) -> Result<SelectItem> {
+        if self.eat_sym("*") {
+            return Ok(SelectItem::Wildcard(None));
+        }
+        if let (Some(Token::Word(w)), Some(Token::Sym("*"))) = (self.peek(), self.tokens.get(self.pos + 1)) {
+            if let Some(table) = w.strip_suffix('.') {
+                let table = table.to_string();
+                self.pos += 2;
+                return Ok(SelectItem::Wildcard(Some(table)));
+            }
+        }
+        let expr = self.expr()?;
+        Ok(SelectItem::Expr(expr, self.alias()?))
+    }
+
+    fn table(&mut self) -> Result<TableRef> {
+        let path = match self.next() {
+            Some(Token::Str(path)) | Some(Token::Ident(path)) | Some(Token::Word(path)) => path,
+            other => anyhow::bail!("Expected a file name, found {:?}", other),
+        };
+        let alias = match self.alias()? {
+            Some(alias) => alias,
+            None => Path::new(&path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.clone()),
+        };
+        Ok(TableRef { path, alias })
+    }
+
+    fn expr(&mut self) -> Result<SqlExpr> {
+        let mut expr = self.and()?;
+        while self.eat_kw("or") {
+            expr = SqlExpr::Binary(Box::new(expr), BinOp::Or, Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<SqlExpr> {
+        let mut expr = self.not()?;
+        while self.eat_kw("and") {
+            expr = SqlExpr::Binary(Box::new(expr), BinOp::And, Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<SqlExpr> {
+        if self.eat_kw("not") {
+            return Ok(SqlExpr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<SqlExpr> {
+        let expr = self.additive()?;
+        if self.eat_kw("is") {
+            let negated = self.eat_kw("not");
+            self.expect_kw("null")?;
+            return Ok(SqlExpr::IsNull(Box::new(expr), negated));
+        }
+        let negated = (self.is_kw(1, "like") || self.is_kw(1, "in")) && self.eat_kw("not");
+        if self.eat_kw("like") {
+            let pattern = match self.next() {
+                Some(Token::Str(pattern)) => pattern,
+                other => anyhow::bail!("Expected a pattern after LIKE, found {:?}", other),
+            };
+            return Ok(SqlExpr::Like(Box::new(expr), like_regex(&pattern)?, negated));
+        }
+        if self.eat_kw("in") {
+            self.expect_sym("(")?;
+            let mut list = Vec::new();
+            loop {
+                list.push(self.additive()?);
+                if !self.eat_sym(",") {
+                    break;
+                }
+            }
+            self.expect_sym(")")?;
+            return Ok(SqlExpr::In(Box::new(expr), list, negated));
+        }
+        let op = match self.peek() {
+            Some(Token::Sym("=")) => BinOp::Eq,
+            Some(Token::Sym("!=")) | Some(Token::Sym("<>")) => BinOp::Ne,
+            Some(Token::Sym("<")) => BinOp::Lt,
+            Some(Token::Sym("<=")) => BinOp::Le,
+            Some(Token::Sym(">")) => BinOp::Gt,
+            Some(Token::Sym(">=")) => BinOp::Ge,
+            _ => return Ok(expr),
+        };
+        self.pos += 1;
+        Ok(SqlExpr::Binary(Box::new(expr), op, Box::new(self.additive()?)))
+    }
+
+    fn additive(&mut self) -> Result<SqlExpr> {
+        let mut expr = self.multiplicative()?;
+        loop {
+            let op = if self.eat_sym("+") {
+                BinOp::Add
+            } else if self.eat_sym("-") {
+                BinOp::Sub
+            } else {
+                return Ok(expr);
+            };
+            expr = SqlExpr::Binary(Box::new(expr), op, Box::new(self.multiplicative()?));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<SqlExpr> {
+        let mut expr = self.unary()?;
+        loop {
+            let op = if self.eat_sym("*") {
+                BinOp::Mul
+            } else if self.eat_sym("/") {
+                BinOp::Div
+            } else {
+                return Ok(expr);
+            };
+            expr = SqlExpr::Binary(Box::new(expr), op, Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<SqlExpr> {
+        if self.eat_sym("-") {
+            return Ok(match self.unary()? {
+                SqlExpr::Literal(Value::Number(n)) => SqlExpr::Literal(arithmetic(&Value::from(0), BinOp::Sub, &Value::Number(n))),
+                e => SqlExpr::Neg(Box::new(e)),
+            });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<SqlExpr> {
+        match self.next() {
+            Some(Token::Sym("(")) => {
+                let expr = self.expr()?;
+                self.expect_sym(")")?;
+                Ok(expr)
+            }
+            Some(Token::Str(s)) => Ok(SqlExpr::Literal(Value::String(s))),
+            Some(Token::Ident(name)) => Ok(SqlExpr::Column(None, name)),
+            Some(Token::Word(word)) => {
+                let lower = word.to_ascii_lowercase();
+                if self.eat_sym("(") {
+                    return self.call(&lower);
+                }
+                match lower.as_str() {
+                    "null" => return Ok(SqlExpr::Literal(Value::Null)),
+                    "true" => return Ok(SqlExpr::Literal(Value::Bool(true))),
+                    "false" => return Ok(SqlExpr::Literal(Value::Bool(false))),
+                    _ => {}
+                }
+                if word.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+                    let n: Number = serde_json::from_str(&word).with_context(|| format!("Invalid number {:?}", word))?;
+                    return Ok(SqlExpr::Literal(Value::Number(n)));
+                }
+                if KEYWORDS.contains(&lower.as_str()) {
+                    anyhow::bail!("Unexpected keyword {}", word.to_uppercase());
+                }
+                // `t."Kit Number"`
+                if let (Some(table), Some(Token::Ident(name))) = (word.strip_suffix('.'), self.peek()) {
+                    let column = SqlExpr::Column(Some(table.to_string()), name.clone());
+                    self.pos += 1;
+                    return Ok(column);
+                }
+                Ok(SqlExpr::Column(None, word))
+            }
+            other => Err(anyhow::anyhow!("Expected an expression, found {:?}", other)),
+        }
+    }
+
+    fn call(&mut self, name: &str) -> Result<SqlExpr> {
+        let agg = name.parse::<AggFunc>().ok();
+        if let Some(agg) = agg {
+            let distinct = self.eat_kw("distinct");
+            let arg = if agg == AggFunc::Count && !distinct && self.eat_sym("*") {
+                None
+            } else {
+                Some(Box::new(self.expr()?))
+            };
+            self.expect_sym(")")?;
+            return Ok(SqlExpr::Agg(agg, arg, distinct));
+        }
+        if !matches!(name, "lower" | "upper" | "length" | "abs" | "round" | "coalesce") {
+            anyhow::bail!("Unknown function {}", name);
+        }
+        let mut args = Vec::new();
+        if !self.eat_sym(")") {
+            loop {
+                args.push(self.expr()?);
+                if !self.eat_sym(",") {
+                    break;
+                }
+            }
+            self.expect_sym(")")?;
+        }
+        Ok(SqlExpr::Func(name.to_string(), args))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn table(path: &str) -> Result<TableRows> {
+        let rows = match path {
+            "players.csv" => json!([
+                {"Name": "Buffon", "Position": "Goalkeeper", "Nationality": "Italy", "Kit Number": 77},
+                {"Name": "Perin", "Position": "Goalkeeper", "Nationality": "Italy", "Kit Number": 37},
+                {"Name": "de Ligt", "Position": "Centre-Back", "Nationality": "Netherlands", "Kit Number": 4},
+                {"Name": "Ronaldo", "Position": "Left Winger", "Nationality": "Portugal", "Kit Number": 7},
+                {"Name": "Higuain", "Position": "Centre-Forward", "Nationality": "Argentina", "Kit Number": null},
+            ]),
+            "countries.csv" => json!([
+                {"Nationality": "Italy", "Continent": "Europe"},
+                {"Nationality": "Portugal", "Continent": "Europe"},
+                {"Nationality": "Argentina", "Continent": "South America"},
+            ]),
+            _ => anyhow::bail!("No table {}", path),
+        };
+        let records: Vec<Record> = rows.as_array().unwrap().iter().map(|r| r.as_object().unwrap().clone()).collect();
+        let headers = records[0].keys().cloned().collect();
+        Ok((headers, Box::new(records.into_iter().map(Ok))))
+    }
+
+    fn run(sql: &str) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+        sql.parse::<Query>()?.execute(table)
+    }
+
+    #[test]
+    fn test_group_by_order_by_position() -> Result<()> {
+        let (headers, rows) = run(
+            "SELECT Nationality, count(*) FROM 'players.csv' GROUP BY Nationality ORDER BY 2 DESC, Nationality",
+        )?;
+        assert_eq!(headers, vec!["Nationality", "count(*)"]);
+        assert_eq!(
+            rows,
+            vec![
+                vec![json!("Italy"), json!(2)],
+                vec![json!("Argentina"), json!(1)],
+                vec![json!("Netherlands"), json!(1)],
+                vec![json!("Portugal"), json!(1)],
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregates() -> Result<()> {
+        let (headers, rows) = run(
+            r#"select count(*) as n, count("Kit Number"), sum("Kit Number"), avg(`Kit Number`) avg_kit,
+                      min(Name), max("Kit Number"), count(distinct Nationality) from players.csv"#,
+        )?;
+        assert_eq!(headers[0], "n");
+        assert_eq!(headers[1], "count(Kit Number)");
+        assert_eq!(headers[3], "avg_kit");
+        assert_eq!(
+            rows,
+            vec![vec![json!(5), json!(4), json!(125), json!(31.25), json!("Buffon"), json!(77), json!(4)]]
+        );
+
+        let (_, rows) = run("SELECT count(*) FROM players.csv WHERE Name = 'Nobody'")?;
+        assert_eq!(rows, vec![vec![json!(0)]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_where_limit_offset() -> Result<()> {
+        let (_, rows) = run(
+            r#"SELECT Name FROM players.csv
+               WHERE "Kit Number" IS NOT NULL AND (Position LIKE 'Centre%' OR "Kit Number" > 30)
+               ORDER BY Name LIMIT 2 OFFSET 1"#,
+        )?;
+        assert_eq!(rows, vec![vec![json!("Perin")], vec![json!("de Ligt")]]);
+
+        let (_, rows) = run("SELECT upper(Name), \"Kit Number\" * 2 AS double FROM players.csv WHERE Nationality NOT IN ('Italy', 'Argentina')")?;
+        assert_eq!(rows, vec![vec![json!("DE LIGT"), json!(8)], vec![json!("RONALDO"), json!(14)]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_join() -> Result<()> {
+        let (headers, rows) = run(
+            "SELECT p.Name, c.Continent FROM players.csv p JOIN countries.csv AS c ON p.Nationality = c.Nationality ORDER BY p.Name",
+        )?;
+        assert_eq!(headers, vec!["Name", "Continent"]);
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0], vec![json!("Buffon"), json!("Europe")]);
+
+        let (_, rows) = run(
+            "SELECT Name, Continent FROM players.csv LEFT JOIN countries.csv ON players.Nationality = countries.Nationality WHERE Continent IS NULL",
+        )?;
+        assert_eq!(rows, vec![vec![json!("de Ligt"), Value::Null]]);
+
+        let (headers, _) = run("SELECT * FROM players.csv JOIN countries.csv ON players.Nationality = countries.Nationality")?;
+        assert_eq!(headers, vec!["Name", "Position", "players.Nationality", "Kit Number", "countries.Nationality", "Continent"]);
+
+        let (_, rows) = run(
+            "SELECT c.Continent, count(*) FROM players.csv p JOIN countries.csv c ON p.Nationality = c.Nationality GROUP BY 1 HAVING count(*) > 1",
+        )?;
+        assert_eq!(rows, vec![vec![json!("Europe"), json!(3)]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_errors() {
+        assert!(run("SELECT Club FROM players.csv").is_err());
+        assert!(run("SELECT Nationality FROM players.csv p JOIN countries.csv c ON p.Nationality = c.Nationality").is_err());
+        assert!(run("SELECT Name FROM players.csv WHERE count(*) > 1").is_err());
+        assert!(run("SELECT Name FROM missing.csv").is_err());
+        assert!("SELECT FROM players.csv".parse::<Query>().is_err());
+        assert!("SELECT Name players.csv".parse::<Query>().is_err());
+        assert!("SELECT Name FROM players.csv ORDER Name".parse::<Query>().is_err());
+        assert!("SELECT foo(Name) FROM players.csv".parse::<Query>().is_err());
+        assert!("SELECT 'Name FROM players.csv".parse::<Query>().is_err());
+    }
+
+    #[test]
+    fn test_query_juventus() -> Result<()> {
+        let query: Query = "SELECT Nationality, count(*) FROM 'assets/juventus.csv' GROUP BY Nationality ORDER BY 2 DESC LIMIT 1".parse()?;
+        let (_, rows) = query.execute(|path| {
+            let source = CsvSource { input: path.into(), from: None, dialect: Default::default(), types: Default::default() };
+            let stream = source_records(&source, "rows")?;
+            Ok((stream.headers, stream.records))
+        })?;
+        assert_eq!(rows[0][0], json!("Italy"));
+        Ok(())
+    }
+}
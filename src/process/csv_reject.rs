@@ -0,0 +1,175 @@
+use std::{cell::RefCell, fmt, io::Write, rc::Rc};
+use anyhow::{Context, Result};
+use csv::ByteRecord;
+
+use crate::{cli::OnError, write_output};
+use super::{
+    csv_convert::{Record, RecordStream},
+    csv_split::csv_line,
+    csv_writer::cell_text,
+};
+
+/// A row that could not be read, typed or mapped. Its fields are kept for the reject file.
+#[derive(Debug)]
+pub struct RejectedRecord {
+    /// Unknown for rows rejected once read, by the mapping
+    pub line: Option<u64>,
+    pub fields: Vec<String>,
+    pub reason: anyhow::Error,
+}
+
+/// Rows set aside under `--on-error skip|quarantine`, and how many were let through.
+pub struct Rejects {
+    on_error: OnError,
+    reject_file: String,
+    delimiter: u8,
+    writer: Option<csv::Writer<Box<dyn Write>>>,
+    accepted: u64,
+    rejected: u64,
+}
+
+/// Records of `stream` with the rejected ones taken out, unless `rejects` fails on them.
+pub fn recover_records<'a>(stream: RecordStream<'a>, rejects: Rc<RefCell<Rejects>>) -> RecordStream<'a> {
+    let records = stream.records.filter_map(move |record| rejects.borrow_mut().check(record));
+    RecordStream { headers: stream.headers, dates: stream.dates, records: Box::new(records) }
+}
+
+impl RejectedRecord {
+    pub fn new(record: &ByteRecord, reason: anyhow::Error) -> Self {
+        Self {
+            line: record.position().map(|p| p.line()),
+            fields: record.iter().map(|field| String::from_utf8_lossy(field).into_owned()).collect(),
+            reason,
+        }
+    }
+
+    /// Reject a record that was read fine, with its values in `headers` order.
+    pub fn from_record(headers: &[String], record: &Record, reason: anyhow::Error) -> Self {
+        let fields = headers.iter().map(|h| record.get(h).map(cell_text).unwrap_or_default().into_owned()).collect();
+        Self { line: None, fields, reason }
+    }
+}
+
+impl fmt::Display for RejectedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "Invalid record at line {}", line),
+            None => write!(f, "Invalid record"),
+        }
+    }
+}
+
+impl std::error::Error for RejectedRecord {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.reason.as_ref())
+    }
+}
+
+impl Rejects {
+    /// `delimiter` is the one of the input, rejected fields are joined with it.
+    pub fn new(on_error: OnError, reject_file: &str, delimiter: u8) -> Result<Self> {
+        let writer = match on_error {
+            OnError::Quarantine => {
+                let file = write_output(reject_file).with_context(|| format!("Cannot create {}", reject_file))?;
+                let mut writer = csv::Writer::from_writer(file);
+                writer.write_record(["line", "reason", "fields"])?;
+                Some(writer)
+            }
+            OnError::Fail | OnError::Skip => None,
+        };
+        Ok(Self { on_error, reject_file: reject_file.to_string(), delimiter, writer, accepted: 0, rejected: 0 })
+    }
+
+    fn check(&mut self, record: Result<Record>) -> Option<Result<Record>> {
+        let error = match record {
+            Ok(record) => {
+                self.accepted += 1;
+                return Some(Ok(record));
+            }
+            Err(e) => e,
+        };
+        match error.downcast_ref::<RejectedRecord>() {
+            Some(rejected) if self.on_error != OnError::Fail => {
+                self.rejected += 1;
+                self.quarantine(rejected).err().map(Err)
+            }
+            _ => Some(Err(error)),
+        }
+    }
+
+    /// The fields are written back as one CSV line with the input delimiter. That is
+    /// the row as parsed, so quoting and spacing may differ from the input.
+    fn quarantine(&mut self, rejected: &RejectedRecord) -> Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            let fields = csv_line(&rejected.fields, self.delimiter)?;
+            let fields = String::from_utf8_lossy(&fields);
+            let line = rejected.line.map(|line| line.to_string()).unwrap_or_default();
+            writer.write_record([&line, &format!("{:#}", rejected.reason), fields.trim_end_matches('\n')])?;
+        }
+        Ok(())
+    }
+
+    /// Flush the reject file and report the counts on stderr.
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        if self.on_error != OnError::Fail {
+            eprintln!("{}", self.summary());
+        }
+        Ok(())
+    }
+
+    fn summary(&self) -> String {
+        match (self.on_error, self.rejected) {
+            (OnError::Quarantine, 1..) => format!("{} rows accepted, {} rejected into {}", self.accepted, self.rejected, self.reject_file),
+            _ => format!("{} rows accepted, {} rejected", self.accepted, self.rejected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cli::CsvDialect, process::csv_convert::csv_records, utils::TempDir};
+    use std::fs;
+
+    const DATA: &str = "Name,Kit Number\nBuffon,77\nChiellini,3,CB\nDybala,10\nBonucci,x\n";
+
+    fn recover(on_error: OnError, reject_file: &str) -> Result<(Vec<Result<Record>>, Rejects)> {
+        let types = crate::cli::CsvTypes { overrides: vec![("Kit Number".into(), crate::cli::ColumnType::Int)], ..Default::default() };
+        let stream = csv_records(DATA.as_bytes(), &CsvDialect::default(), &types)?;
+        let rejects = Rc::new(RefCell::new(Rejects::new(on_error, reject_file, b',')?));
+        let records = recover_records(stream, rejects.clone()).records.collect();
+        let rejects = Rc::into_inner(rejects).expect("the stream is gone").into_inner();
+        Ok((records, rejects))
+    }
+
+    #[test]
+    fn test_on_error() -> Result<()> {
+        let (records, _) = recover(OnError::Fail, "-")?;
+        let err = records[1].as_ref().unwrap_err();
+        assert_eq!(err.to_string(), "Invalid record at line 3");
+        assert_eq!(format!("{:#}", err), "Invalid record at line 3: found 3 fields instead of 2");
+
+        let (records, rejects) = recover(OnError::Skip, "-")?;
+        let names: Vec<_> = records.into_iter().map(|r| r.map(|r| r["Name"].clone())).collect::<Result<_>>()?;
+        assert_eq!(names, ["Buffon", "Dybala"]);
+        assert_eq!(rejects.summary(), "2 rows accepted, 2 rejected");
+        Ok(())
+    }
+
+    #[test]
+    fn test_quarantine() -> Result<()> {
+        let dir = TempDir::new("rejects")?;
+        let (records, mut rejects) = recover(OnError::Quarantine, &dir.path("rejects.csv"))?;
+        assert_eq!(records.len(), 2);
+        rejects.finish()?;
+        let content = fs::read_to_string(dir.path("rejects.csv"))?;
+        let mut lines = content.lines();
+        assert_eq!(lines.next(), Some("line,reason,fields"));
+        assert_eq!(lines.next(), Some("3,found 3 fields instead of 2,\"Chiellini,3,CB\""));
+        assert!(lines.next().is_some_and(|line| line.starts_with("5,") && line.ends_with(",\"Bonucci,x\"")));
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+use anyhow::Result;
+use serde_json::Value;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::cli::CsvSource;
+use super::{csv_convert::{source_records, Record}, csv_writer::cell_text};
+
+pub fn process_csv_show(source: &CsvSource, offset: usize, head: Option<usize>, tail: Option<usize>, max_width: usize) -> Result<String> {
+    let stream = source_records(source, "rows")?;
+    let mut records = stream.records.skip(offset);
+    let rows: Vec<Record> = match (head, tail) {
+        (Some(n), _) => records.by_ref().take(n).collect::<Result<_>>()?,
+        (None, Some(n)) => {
+            let mut last = VecDeque::with_capacity(n + 1);
+            for record in records {
+                last.push_back(record?);
+                if last.len() > n {
+                    last.pop_front();
+                }
+            }
+            last.into()
+        }
+        (None, None) => records.collect::<Result<_>>()?,
+    };
+
+    let rows: Vec<Vec<Value>> = rows
+        .into_iter()
+        .map(|mut record| {
+            stream
+                .headers
+                .iter()
+                .map(|h| record.remove(h).unwrap_or(Value::Null))
+                .collect()
+        })
+        .collect();
+    Ok(render_table(&stream.headers, &rows, max_width))
+}
+
+/// Draw rows as a table with box-drawing borders. Numbers are right aligned and
+/// cells wider than `max_width` are cut with an ellipsis.
+pub fn render_table(headers: &[String], rows: &[Vec<Value>], max_width: usize) -> String {
+    if headers.is_empty() {
+        return String::new();
+    }
+    let header_cells: Vec<String> = headers.iter().map(|h| truncate(h, max_width)).collect();
+    let cells: Vec<Vec<(String, bool)>> = rows
+        .iter()
+        .map(|row| {
+            (0..headers.len())
+                .map(|i| match row.get(i) {
+                    Some(value) => (truncate(&cell_text(value), max_width), value.is_number()),
+                    None => (String::new(), false),
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = header_cells.iter().map(|h| h.width()).collect();
+    for row in &cells {
+        for (width, (text, _)) in widths.iter_mut().zip(row) {
+            *width = (*width).max(text.width());
+        }
+    }
+
+    let mut ret = String::new();
+    border(&mut ret, &widths, '┌', '┬', '┐');
+    line(&mut ret, &widths, header_cells.iter().map(|h| (h.as_str(), false)));
+    border(&mut ret, &widths, '├', '┼', '┤');
+    for row in &cells {
+        line(&mut ret, &widths, row.iter().map(|(text, right)| (text.as_str(), *right)));
+    }
+    border(&mut ret, &widths, '└', '┴', '┘');
+    ret
+}
+
+fn border(ret: &mut String, widths: &[usize], left: char, middle: char, right: char) {
+    ret.push(left);
+    for (i, width) in widths.iter().enumerate() {
+        if i > 0 {
+            ret.push(middle);
+        }
+        ret.push_str(&"─".repeat(width + 2));
+    }
+    ret.push(right);
+    ret.push('\n');
+}
+
+fn line<'a>(ret: &mut String, widths: &[usize], cells: impl Iterator<Item = (&'a str, bool)>) {
+    ret.push('│');
+    for ((text, right), width) in cells.zip(widths) {
+        let pad = " ".repeat(width - text.width());
+        if right {
+            ret.push_str(&format!(" {}{} │", pad, text));
+        } else {
+            ret.push_str(&format!(" {}{} │", text, pad));
+        }
+    }
+    ret.push('\n');
+}
+
+/// Cut `s` to at most `max_width` columns, control characters become spaces.
+fn truncate(s: &str, max_width: usize) -> String {
+    let s: String = s.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+    if s.width() <= max_width {
+        return s;
+    }
+    let mut ret = String::new();
+    let mut width = 0;
+    for c in s.chars() {
+        let w = c.width().unwrap_or(0);
+        if width + w + 1 > max_width {
+            break;
+        }
+        ret.push(c);
+        width += w;
+    }
+    ret.push('…');
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_table() {
+        let headers = vec!["Name".to_string(), "Kit".to_string()];
+        let rows = vec![
+            vec![json!("Buffon"), json!(77)],
+            vec![json!("Cristiano Ronaldo"), json!(7)],
+        ];
+        let table = render_table(&headers, &rows, 10);
+        assert_eq!(
+            table,
+            "┌────────────┬─────┐\n\
+             │ Name       │ Kit │\n\
+             ├────────────┼─────┤\n\
+             │ Buffon     │  77 │\n\
+             │ Cristiano… │   7 │\n\
+             └────────────┴─────┘\n"
+        );
+    }
+
+    #[test]
+    fn test_truncate_wide_chars() {
+        assert_eq!(truncate("尤文图斯俱乐部", 7), "尤文图…");
+        assert_eq!(truncate("a\tb", 7), "a b");
+    }
+
+    #[test]
+    fn test_show_paging() -> Result<()> {
+        let source = CsvSource {
+            input: "assets/juventus.csv".into(),
+            from: None,
+            dialect: Default::default(),
+            types: Default::default(),
+        };
+        let table = process_csv_show(&source, 1, Some(2), None, 40)?;
+        assert_eq!(table.lines().count(), 6);
+        assert!(table.contains("Mattia Perin"));
+        assert!(!table.contains("Wojciech Szczesny"));
+
+        let table = process_csv_show(&source, 0, None, Some(1), 40)?;
+        assert_eq!(table.lines().count(), 5);
+        Ok(())
+    }
+}
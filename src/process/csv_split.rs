@@ -0,0 +1,319 @@
+use std::{collections::{HashMap, HashSet, VecDeque}, fs::{self, File, OpenOptions}, io::{BufWriter, Write}, path::Path};
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::cli::{CsvSource, CsvTypes, OutputOpts, SplitBy};
+use super::{
+    csv_convert::{source_records, RecordStream},
+    csv_nested::reshape_records,
+    csv_writer::{cell_text, write_csv_stream},
+};
+
+/// Parts of `--column` kept open at once, the least recently written is closed first
+/// and reopened to append when its value comes back.
+const MAX_OPEN_PARTS: usize = 64;
+
+struct Part {
+    path: String,
+    writer: Option<BufWriter<File>>,
+    rows: usize,
+    bytes: u64,
+}
+
+/// Split `source` into CSV parts in `dir`, each starting with the header. Returns the
+/// paths written. Fields are copied as text, without type inference.
+pub fn process_csv_split(source: &CsvSource, by: &SplitBy, dir: &str, prefix: Option<&str>) -> Result<Vec<String>> {
+    let source = CsvSource {
+        types: CsvTypes { infer: false, overrides: Vec::new(), ..source.types.clone() },
+        ..source.clone()
+    };
+    let stream = source_records(&source, "rows")?;
+    match by {
+        SplitBy::Rows(0) => anyhow::bail!("--rows must be more than 0"),
+        SplitBy::Column(column) if !stream.headers.contains(column) => anyhow::bail!("Unknown column {:?}", column),
+        _ => {}
+    }
+    let prefix = prefix.map(String::from).unwrap_or_else(|| default_prefix(&source.input));
+    let delimiter = source.dialect.delimiter;
+    let extension = if delimiter == b'\t' { "tsv" } else { "csv" };
+    fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir))?;
+    let header = csv_line(&stream.headers, delimiter)?;
+
+    let mut parts: Vec<Part> = Vec::new();
+    let mut by_value: HashMap<String, usize> = HashMap::new();
+    let mut names: HashSet<String> = HashSet::new();
+    // indices of the open parts of `--column`, least recently written first
+    let mut open: VecDeque<usize> = VecDeque::new();
+    for record in stream.records {
+        let record = record?;
+        let fields: Vec<_> = stream.headers.iter().map(|h| record.get(h).map(cell_text).unwrap_or_default()).collect();
+        let line = csv_line(&fields, delimiter)?;
+        let next = parts.len() + 1;
+        let index = match by {
+            SplitBy::Rows(rows) if parts.last().is_some_and(|p| p.rows < *rows) => parts.len() - 1,
+            SplitBy::Bytes(bytes) if parts.last().is_some_and(|p| p.rows == 0 || p.bytes + line.len() as u64 <= *bytes) => {
+                parts.len() - 1
+            }
+            SplitBy::Rows(_) | SplitBy::Bytes(_) => {
+                if let Some(last) = parts.last_mut() {
+                    last.close()?;
+                }
+                parts.push(Part::create(dir, &format!("{}-{}.{}", prefix, next, extension), &header)?);
+                parts.len() - 1
+            }
+            SplitBy::Column(column) => {
+                let value = record.get(column).map(cell_text).unwrap_or_default();
+                let index = match by_value.get(value.as_ref()) {
+                    Some(i) => *i,
+                    None => {
+                        if open.len() == MAX_OPEN_PARTS {
+                            if let Some(i) = open.pop_front() {
+                                parts[i].close()?;
+                            }
+                        }
+                        let name = unique_name(&mut names, &format!("{}-{}", prefix, file_safe(&value)), extension);
+                        parts.push(Part::create(dir, &name, &header)?);
+                        by_value.insert(value.into_owned(), parts.len() - 1);
+                        parts.len() - 1
+                    }
+                };
+                match open.iter().position(|i| *i == index) {
+                    Some(at) => {
+                        open.remove(at);
+                    }
+                    None if open.len() == MAX_OPEN_PARTS => {
+                        if let Some(i) = open.pop_front() {
+                            parts[i].close()?;
+                        }
+                    }
+                    None => {}
+                }
+                open.push_back(index);
+                index
+            }
+        };
+        parts[index].write(&line)?;
+    }
+
+    let mut paths = Vec::new();
+    for mut part in parts {
+        part.close()?;
+        paths.push(part.path);
+    }
+    Ok(paths)
+}
+
+/// Concatenate `sources` into `output`. The header is the union of theirs in the
+/// order first seen, records get nulls for the columns their file lacks. Fields are
+/// copied as text unless typed with `--type`.
+pub fn process_csv_merge(sources: &[CsvSource], output: &str, out: &OutputOpts) -> Result<()> {
+    let streams = sources
+        .iter()
+        .map(|source| {
+            let source = CsvSource { types: CsvTypes { infer: false, ..source.types.clone() }, ..source.clone() };
+            source_records(&source, &out.root).with_context(|| format!("Cannot read {}", source.input))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    write_csv_stream(reshape_records(merge_streams(streams), out)?, output, out)
+}
+
+pub fn merge_streams<'a>(streams: Vec<RecordStream<'a>>) -> RecordStream<'a> {
+    let mut headers: Vec<String> = Vec::new();
+    for header in streams.iter().flat_map(|s| &s.headers) {
+        if !headers.contains(header) {
+            headers.push(header.clone());
+        }
+    }
+    let mut dates: Vec<String> = Vec::new();
+    for date in streams.iter().flat_map(|s| &s.dates) {
+        if !dates.contains(date) {
+            dates.push(date.clone());
+        }
+    }
+    let columns = headers.clone();
+    let records = streams.into_iter().flat_map(|s| s.records).map(move |record| {
+        let mut record = record?;
+        Ok(columns.iter().map(|c| (c.clone(), record.remove(c).unwrap_or(Value::Null))).collect())
+    });
+    RecordStream { headers, dates, records: Box::new(records) }
+}
+
+impl Part {
+    fn create(dir: &str, name: &str, header: &[u8]) -> Result<Self> {
+        let path = Path::new(dir).join(name).to_string_lossy().into_owned();
+        let file = File::create(&path).with_context(|| format!("Cannot create {}", path))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(header)?;
+        Ok(Self { path, writer: Some(writer), rows: 0, bytes: header.len() as u64 })
+    }
+
+    fn write(&mut self, line: &[u8]) -> Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let file = OpenOptions::new().append(true).open(&self.path).with_context(|| format!("Cannot reopen {}", self.path))?;
+                self.writer.insert(BufWriter::new(file))
+            }
+        };
+        writer.write_all(line)?;
+        self.rows += 1;
+        self.bytes += line.len() as u64;
+        Ok(())
+    }
+
+    /// Flush and close the file, `write` opens it again.
+    fn close(&mut self) -> Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn csv_line<T: AsRef<str>>(fields: &[T], delimiter: u8) -> Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new().delimiter(delimiter).from_writer(Vec::new());
+    writer.write_record(fields.iter().map(|f| f.as_ref()))?;
+    writer.into_inner().map_err(|e| anyhow::anyhow!("{}", e.error()))
+}
+
+/// `juventus` for `data/juventus.csv.gz`, `part` for stdin.
+fn default_prefix(input: &str) -> String {
+    match Path::new(input).file_name().and_then(|name| name.to_str()) {
+        Some(name) if input != "-" => name.split('.').next().unwrap_or(name).to_string(),
+        _ => "part".to_string(),
+    }
+}
+
+/// `{base}.{extension}`, or `{base}-2.{extension}` and so on when values gave the same name.
+fn unique_name(names: &mut HashSet<String>, base: &str, extension: &str) -> String {
+    let mut name = format!("{}.{}", base, extension);
+    let mut n = 1;
+    while !names.insert(name.clone()) {
+        n += 1;
+        name = format!("{}-{}.{}", base, n, extension);
+    }
+    name
+}
+
+/// A column value usable in a file name, `_` for anything else.
+fn file_safe(value: &str) -> String {
+    match value {
+        "" => "_".to_string(),
+        _ => value
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use serde_json::json;
+
+    fn split(by: SplitBy) -> Result<Vec<(String, String)>> {
+        split_file("assets/juventus.csv", by)
+    }
+
+    fn split_file(input: &str, by: SplitBy) -> Result<Vec<(String, String)>> {
+        let dir = TempDir::new("split")?;
+        let source = CsvSource {
+            input: input.into(),
+            from: None,
+            dialect: Default::default(),
+            types: Default::default(),
+        };
+        let paths = process_csv_split(&source, &by, &dir.path("parts"), None)?;
+        paths
+            .iter()
+            .map(|p| Ok((Path::new(p).file_name().unwrap().to_string_lossy().into_owned(), fs::read_to_string(p)?)))
+            .collect()
+    }
+
+    #[test]
+    fn test_split() -> Result<()> {
+        let header = "Name,Position,DOB,Nationality,Kit Number\n";
+        let by_rows = split(SplitBy::Rows(10))?;
+        assert_eq!(by_rows.len(), 3);
+        assert_eq!(by_rows[0].0, "juventus-1.csv");
+        assert!(by_rows.iter().all(|(_, content)| content.starts_with(header)));
+        assert_eq!(by_rows[0].1.lines().count(), 11);
+
+        let by_bytes = split(SplitBy::Bytes(500))?;
+        assert!(by_bytes.len() > 1);
+        assert!(by_bytes.iter().all(|(_, content)| content.len() <= 500));
+
+        let by_position = split(SplitBy::Column("Position".into()))?;
+        assert_eq!(by_position[0].0, "juventus-Goalkeeper.csv");
+        assert!(by_position[0].1.lines().skip(1).all(|line| line.contains(",Goalkeeper,")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_colliding_names() -> Result<()> {
+        let dir = TempDir::new("split-input")?;
+        fs::write(dir.path("players.csv"), "Name,Position\nBonucci,Centre Back\nChiellini,Centre_Back\nDe Ligt,Centre Back\n")?;
+        let parts = split_file(&dir.path("players.csv"), SplitBy::Column("Position".into()))?;
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].0, "players-Centre_Back.csv");
+        assert_eq!(parts[0].1, "Name,Position\nBonucci,Centre Back\nDe Ligt,Centre Back\n");
+        assert_eq!(parts[1].0, "players-Centre_Back-2.csv");
+        assert_eq!(parts[1].1, "Name,Position\nChiellini,Centre_Back\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_many_values() -> Result<()> {
+        let dir = TempDir::new("split-input")?;
+        let rows: String = (0..MAX_OPEN_PARTS * 4).map(|i| format!("p{},{}\n", i, i % (MAX_OPEN_PARTS * 2))).collect();
+        fs::write(dir.path("groups.csv"), format!("Name,Group\n{}", rows))?;
+        let parts = split_file(&dir.path("groups.csv"), SplitBy::Column("Group".into()))?;
+        assert_eq!(parts.len(), MAX_OPEN_PARTS * 2);
+        assert_eq!(parts[1].1, format!("Name,Group\np1,1\np{},1\n", MAX_OPEN_PARTS * 2 + 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_keeps_text() -> Result<()> {
+        let dir = TempDir::new("merge")?;
+        fs::write(dir.path("a.csv"), "Name,DOB,Height\nBuffon,1978/01/28,1.92\n")?;
+        fs::write(dir.path("b.csv"), "Name,Height,Captain\nChiellini,1.50,TRUE\n")?;
+        let source = |input: String| CsvSource {
+            input,
+            from: None,
+            dialect: Default::default(),
+            types: Default::default(),
+        };
+        process_csv_merge(&[source(dir.path("a.csv")), source(dir.path("b.csv"))], &dir.path("merged"), &Default::default())?;
+        assert_eq!(fs::read_to_string(dir.path("merged"))?, "Name,DOB,Height,Captain\nBuffon,1978/01/28,1.92,\nChiellini,,1.50,TRUE\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_streams() -> Result<()> {
+        let stream = |headers: &[&str], records: Value| RecordStream {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            dates: Vec::new(),
+            records: Box::new(records.as_array().unwrap().clone().into_iter().map(|r| Ok(r.as_object().unwrap().clone()))),
+        };
+        let merged = merge_streams(vec![
+            stream(&["Name", "Position"], json!([{"Name": "Buffon", "Position": "Goalkeeper"}])),
+            stream(&["Name", "Kit Number"], json!([{"Name": "Dybala", "Kit Number": 10}])),
+        ]);
+        assert_eq!(merged.headers, vec!["Name", "Position", "Kit Number"]);
+        let records = merged.records.collect::<Result<Vec<_>>>()?;
+        assert_eq!(Value::Object(records[1].clone()), json!({"Name": "Dybala", "Position": null, "Kit Number": 10}));
+        assert_eq!(records[1].keys().collect::<Vec<_>>(), vec!["Name", "Position", "Kit Number"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_names() {
+        assert_eq!(default_prefix("data/juventus.csv.gz"), "juventus");
+        assert_eq!(default_prefix("-"), "part");
+        assert_eq!(file_safe("Centre-Back"), "Centre-Back");
+        assert_eq!(file_safe("a/b c"), "a_b_c");
+        assert_eq!(file_safe(""), "_");
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use anyhow::Result;
+use serde_json::{json, Map, Number, Value};
+
+use crate::cli::{CsvSource, SortMode, StatsFormat};
+use super::{csv_convert::source_records, csv_filter::compare_values, csv_infer::parse_date, csv_show::render_table, csv_writer::cell_text};
+
+/// Running statistics of one column, numbers use Welford's algorithm for mean and variance.
+#[derive(Debug, Default)]
+struct ColumnStats {
+    name: String,
+    count: usize,
+    nulls: usize,
+    ints: usize,
+    floats: usize,
+    bools: usize,
+    dates: usize,
+    min: Option<Value>,
+    max: Option<Value>,
+    mean: f64,
+    m2: f64,
+    max_length: usize,
+    // value text -> (count, first seen)
+    frequencies: HashMap<String, (usize, usize)>,
+}
+
+pub fn process_csv_stats(source: &CsvSource, top: usize, format: StatsFormat) -> Result<String> {
+    let mut stream = source_records(source, "rows")?;
+    let mut columns: Vec<ColumnStats> = stream
+        .headers
+        .iter()
+        .map(|name| ColumnStats { name: name.clone(), ..Default::default() })
+        .collect();
+    let mut rows = 0;
+    for record in stream.records.by_ref() {
+        let record = record?;
+        for column in columns.iter_mut() {
+            column.push(record.get(&column.name).unwrap_or(&Value::Null));
+        }
+        rows += 1;
+    }
+
+    let reports: Vec<Map<String, Value>> = columns.iter().map(|c| c.report(top)).collect();
+    match format {
+        StatsFormat::Json => {
+            let report = json!({ "rows": rows, "columns": reports });
+            Ok(format!("{}\n", serde_json::to_string_pretty(&report)?))
+        }
+        StatsFormat::Table => {
+            let headers: Vec<String> = ["column", "type", "nulls", "distinct", "min", "max", "mean", "stddev", "max length", "top"]
+                .iter()
+                .map(|h| h.to_string())
+                .collect();
+            let table: Vec<Vec<Value>> = reports
+                .into_iter()
+                .map(|mut report| {
+                    let top = report
+                        .remove("top")
+                        .and_then(|top| top.as_array().cloned())
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|t| format!("{} ({})", cell_text(&t["value"]), t["count"]))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let mut row: Vec<Value> = ["column", "type", "nulls", "distinct", "min", "max", "mean", "stddev", "max_length"]
+                        .iter()
+                        .map(|key| report.remove(*key).unwrap_or(Value::Null))
+                        .collect();
+                    row.push(Value::String(top));
+                    row
+                })
+                .collect();
+            Ok(format!("{} rows\n{}", rows, render_table(&headers, &table, 40)))
+        }
+    }
+}
+
+impl ColumnStats {
+    fn push(&mut self, value: &Value) {
+        if value.is_null() || value.as_str() == Some("") {
+            self.nulls += 1;
+            return;
+        }
+        self.count += 1;
+        match value {
+            Value::Number(n) if n.is_i64() || n.is_u64() => self.ints += 1,
+            Value::Number(_) => self.floats += 1,
+            Value::Bool(_) => self.bools += 1,
+            Value::String(s) if parse_date(s).is_some() => self.dates += 1,
+            _ => {}
+        }
+        if let Some(n) = value.as_f64() {
+            let delta = n - self.mean;
+            self.mean += delta / (self.ints + self.floats) as f64;
+            self.m2 += delta * (n - self.mean);
+        }
+        if self.min.as_ref().is_none_or(|min| compare_values(value, min, SortMode::Auto).is_lt()) {
+            self.min = Some(value.clone());
+        }
+        if self.max.as_ref().is_none_or(|max| compare_values(value, max, SortMode::Auto).is_gt()) {
+            self.max = Some(value.clone());
+        }
+        let text = cell_text(value);
+        self.max_length = self.max_length.max(text.chars().count());
+        let seen = self.frequencies.len();
+        self.frequencies.entry(text.into_owned()).or_insert((0, seen)).0 += 1;
+    }
+
+    /// The narrowest type holding every non-null value, `mixed` when they don't agree.
+    fn ty(&self) -> &'static str {
+        let numbers = self.ints + self.floats;
+        match self.count {
+            0 => "empty",
+            n if n == self.ints => "int",
+            n if n == numbers => "float",
+            n if n == self.bools => "bool",
+            n if n == self.dates => "date",
+            _ if numbers + self.bools > 0 => "mixed",
+            _ => "string",
+        }
+    }
+
+    fn report(&self, top: usize) -> Map<String, Value> {
+        let numbers = self.ints + self.floats;
+        let float = |f: f64| Number::from_f64(f).map_or(Value::Null, Value::Number);
+        let mut frequent: Vec<(&String, &(usize, usize))> = self.frequencies.iter().collect();
+        frequent.sort_by(|(_, (a, i)), (_, (b, j))| b.cmp(a).then(i.cmp(j)));
+        let top: Vec<Value> = frequent
+            .into_iter()
+            .take(top)
+            .map(|(value, (count, _))| json!({ "value": value, "count": count }))
+            .collect();
+
+        let mut report = Map::new();
+        report.insert("column".into(), Value::String(self.name.clone()));
+        report.insert("type".into(), Value::String(self.ty().into()));
+        report.insert("count".into(), Value::from(self.count));
+        report.insert("nulls".into(), Value::from(self.nulls));
+        report.insert("distinct".into(), Value::from(self.frequencies.len()));
+        report.insert("min".into(), self.min.clone().unwrap_or(Value::Null));
+        report.insert("max".into(), self.max.clone().unwrap_or(Value::Null));
+        report.insert("mean".into(), if numbers > 0 { float(round(self.mean)) } else { Value::Null });
+        // sample standard deviation
+        let stddev = if numbers > 1 { float(round((self.m2 / (numbers - 1) as f64).sqrt())) } else { Value::Null };
+        report.insert("stddev".into(), stddev);
+        report.insert("max_length".into(), Value::from(self.max_length));
+        report.insert("top".into(), Value::Array(top));
+        report
+    }
+}
+
+fn round(f: f64) -> f64 {
+    (f * 1e4).round() / 1e4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(values: &[Value]) -> Map<String, Value> {
+        let mut column = ColumnStats { name: "c".into(), ..Default::default() };
+        for value in values {
+            column.push(value);
+        }
+        column.report(2)
+    }
+
+    #[test]
+    fn test_numeric_column() {
+        let report = stats(&[json!(2), json!(4), Value::Null, json!(4), json!(""), json!(5), json!(5), json!(7), json!(9)]);
+        assert_eq!(report["type"], json!("int"));
+        assert_eq!(report["count"], json!(7));
+        assert_eq!(report["nulls"], json!(2));
+        assert_eq!(report["distinct"], json!(5));
+        assert_eq!(report["min"], json!(2));
+        assert_eq!(report["max"], json!(9));
+        assert_eq!(report["mean"], json!(5.1429));
+        assert_eq!(report["stddev"], json!(2.2678));
+        assert_eq!(report["top"], json!([{"value": "4", "count": 2}, {"value": "5", "count": 2}]));
+    }
+
+    #[test]
+    fn test_column_types() {
+        assert_eq!(stats(&[json!(1), json!(1.5)])["type"], json!("float"));
+        assert_eq!(stats(&[json!("2019-01-28"), json!("Jan 28, 1978")])["type"], json!("date"));
+        assert_eq!(stats(&[json!(1), json!("x")])["type"], json!("mixed"));
+        assert_eq!(stats(&[Value::Null])["type"], json!("empty"));
+
+        let report = stats(&[json!("Buffon"), json!("de Ligt")]);
+        assert_eq!(report["type"], json!("string"));
+        assert_eq!(report["max_length"], json!(7));
+        assert_eq!(report["mean"], Value::Null);
+    }
+
+    #[test]
+    fn test_stats_juventus() -> Result<()> {
+        let source = CsvSource {
+            input: "assets/juventus.csv".into(),
+            from: None,
+            dialect: Default::default(),
+            types: Default::default(),
+        };
+        let report: Value = serde_json::from_str(&process_csv_stats(&source, 3, StatsFormat::Json)?)?;
+        let nationality = &report["columns"][3];
+        assert_eq!(nationality["column"], json!("Nationality"));
+        assert_eq!(nationality["top"][0], json!({"value": "Italy", "count": 8}));
+
+        let table = process_csv_stats(&source, 3, StatsFormat::Table)?;
+        assert!(table.contains("Kit Number"));
+        Ok(())
+    }
+}
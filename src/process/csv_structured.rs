@@ -0,0 +1,141 @@
+use std::io::{BufRead, BufReader, Read};
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+
+use crate::cli::Inputformat;
+use super::csv_convert::{Record, RecordStream};
+
+/// Read an array of objects from JSON, NDJSON, YAML or TOML. Nested objects are
+/// flattened into dotted column names and the header is the union of all keys,
+/// in the order they are first seen.
+pub fn structured_records<'a>(mut reader: Box<dyn Read>, format: Inputformat, table: &str) -> Result<RecordStream<'a>> {
+    let values = match format {
+        Inputformat::Json => {
+            let value: Value = serde_json::from_reader(reader)?;
+            to_rows(value)
+        }
+        Inputformat::Ndjson => BufReader::new(reader)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !matches!(line, Ok(l) if l.trim().is_empty()))
+            .map(|(i, line)| serde_json::from_str(&line?).with_context(|| format!("Invalid JSON at line {}", i + 1)))
+            .collect::<Result<Vec<Value>>>()?,
+        Inputformat::Yaml => {
+            let value: Value = serde_yaml::from_reader(reader)?;
+            to_rows(value)
+        }
+        Inputformat::Toml => {
+            let mut buf = String::new();
+            reader.read_to_string(&mut buf)?;
+            let doc: toml::Table = toml::from_str(&buf)?;
+            toml_rows(doc, table)?
+        }
+        Inputformat::Csv | Inputformat::Xlsx | Inputformat::Fixed => anyhow::bail!("{} is not a structured format", format),
+    };
+
+    let mut headers: Vec<String> = Vec::new();
+    let mut records = Vec::with_capacity(values.len());
+    for (i, value) in values.into_iter().enumerate() {
+        let Value::Object(object) = value else {
+            anyhow::bail!("Record {} is not an object", i + 1);
+        };
+        let mut record = Record::new();
+        flatten_object("", object, &mut record);
+        for key in record.keys() {
+            if !headers.contains(key) {
+                headers.push(key.clone());
+            }
+        }
+        records.push(Ok(record));
+    }
+    Ok(RecordStream {
+        headers,
+        dates: Vec::new(),
+        records: Box::new(records.into_iter()),
+    })
+}
+
+/// A top level array holds the records, anything else is a single record.
+fn to_rows(value: Value) -> Vec<Value> {
+    match value {
+        Value::Array(rows) => rows,
+        Value::Null => Vec::new(),
+        value => vec![value],
+    }
+}
+
+/// TOML documents are tables: take the array of tables named `table`, or the only array there is.
+fn toml_rows(doc: toml::Table, table: &str) -> Result<Vec<Value>> {
+    let doc = match serde_json::to_value(doc)? {
+        Value::Object(doc) => doc,
+        _ => unreachable!("a TOML document is always a table"),
+    };
+    let arrays: Vec<_> = doc.iter().filter(|(_, v)| v.is_array()).collect();
+    let rows = match (doc.get(table), arrays.as_slice()) {
+        (Some(rows @ Value::Array(_)), _) => rows.clone(),
+        (_, [(_, rows)]) => (*rows).clone(),
+        _ => anyhow::bail!("Expected an array of tables named {:?}", table),
+    };
+    Ok(to_rows(rows))
+}
+
+fn flatten_object(prefix: &str, object: Map<String, Value>, out: &mut Map<String, Value>) {
+    if object.is_empty() && !prefix.is_empty() {
+        out.insert(prefix.to_string(), Value::Null);
+    }
+    for (key, value) in object {
+        let key = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+        match value {
+            Value::Object(object) => flatten_object(&key, object, out),
+            value => {
+                out.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn read(data: &'static str, format: Inputformat) -> Result<(Vec<String>, Vec<Record>)> {
+        let stream = structured_records(Box::new(data.as_bytes()), format, "rows")?;
+        let records = stream.records.collect::<Result<Vec<_>>>()?;
+        Ok((stream.headers, records))
+    }
+
+    #[test]
+    fn test_json_flatten_and_union() -> Result<()> {
+        let (headers, records) = read(
+            r#"[{"name": "Buffon", "club": {"name": "Juventus", "city": "Turin"}},
+                {"name": "Dybala", "kit": 10, "tags": ["a", "b"]}]"#,
+            Inputformat::Json,
+        )?;
+        assert_eq!(headers, vec!["name", "club.name", "club.city", "kit", "tags"]);
+        assert_eq!(records[0]["club.city"], json!("Turin"));
+        assert_eq!(records[1]["tags"], json!(["a", "b"]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ndjson_yaml_toml() -> Result<()> {
+        let (headers, records) = read("{\"a\": 1}\n\n{\"b\": 2}\n", Inputformat::Ndjson)?;
+        assert_eq!(headers, vec!["a", "b"]);
+        assert_eq!(records.len(), 2);
+
+        let (headers, records) = read("- a: 1\n  b: {c: x}\n", Inputformat::Yaml)?;
+        assert_eq!(headers, vec!["a", "b.c"]);
+        assert_eq!(records[0]["b.c"], json!("x"));
+
+        let (headers, records) = read("[[players]]\nname = \"Buffon\"\n\n[[players]]\nname = \"Dybala\"\n", Inputformat::Toml)?;
+        assert_eq!(headers, vec!["name"]);
+        assert_eq!(records.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_object_record() {
+        assert!(read("[1, 2]", Inputformat::Json).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use std::{fmt, fs};
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::cli::{ColumnType, CsvSource, CsvTypes};
+use super::{csv_convert::source_records, csv_infer::{parse_date, parse_field}, csv_reject::RejectedRecord, csv_writer::cell_text};
+
+/// Expected columns of a file, read from TOML (`[[columns]]`) or JSON (`{"columns": [...]}`).
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    pub columns: Vec<ColumnSchema>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnSchema {
+    pub name: String,
+    #[serde(rename = "type", default = "string_type", deserialize_with = "column_type")]
+    pub ty: ColumnType,
+    /// The column must be present and no value may be empty
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default, with = "serde_regex")]
+    pub pattern: Option<Regex>,
+    /// Allowed values
+    #[serde(rename = "enum")]
+    pub values: Option<Vec<String>>,
+    /// Bounds of numbers, or of dates written as `YYYY-MM-DD`
+    pub min: Option<Bound>,
+    pub max: Option<Bound>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Bound {
+    Number(f64),
+    Date(String),
+}
+
+/// One failed check. Rows count data rows from 1, row 0 is the header. The column is
+/// empty for rows that could not be read at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub row: usize,
+    pub column: String,
+    pub message: String,
+}
+
+pub fn process_csv_validate(source: &CsvSource, schema: &str) -> Result<Vec<Violation>> {
+    let schema = Schema::load(schema)?;
+    // check the text as written, not what inference makes of it
+    let source = CsvSource {
+        types: CsvTypes { infer: false, overrides: Vec::new(), ..source.types.clone() },
+        ..source.clone()
+    };
+    let stream = source_records(&source, "rows")?;
+
+    let mut violations = Vec::new();
+    for column in &schema.columns {
+        if column.required && !stream.headers.contains(&column.name) {
+            violations.push(Violation { row: 0, column: column.name.clone(), message: "required column is missing".into() });
+        }
+    }
+    for (i, record) in stream.records.enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => match e.downcast::<RejectedRecord>() {
+                Ok(rejected) => {
+                    let message = format!("{:#}", rejected.reason);
+                    violations.push(Violation { row: i + 1, column: String::new(), message });
+                    continue;
+                }
+                Err(e) => return Err(e),
+            },
+        };
+        for column in &schema.columns {
+            let Some(value) = record.get(&column.name) else {
+                continue;
+            };
+            if let Some(message) = column.check(&cell_text(value)) {
+                violations.push(Violation { row: i + 1, column: column.name.clone(), message });
+            }
+        }
+    }
+    Ok(violations)
+}
+
+impl Schema {
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| format!("Cannot read schema {}", path))?;
+        let schema: Schema = if path.to_ascii_lowercase().ends_with(".json") {
+            serde_json::from_str(&content).with_context(|| format!("Invalid schema {}", path))?
+        } else {
+            toml::from_str(&content).with_context(|| format!("Invalid schema {}", path))?
+        };
+        for column in &schema.columns {
+            for bound in column.min.iter().chain(&column.max) {
+                match (column.ty, bound) {
+                    (ColumnType::Int | ColumnType::Float, Bound::Number(_)) => {}
+                    (ColumnType::Date, Bound::Date(date)) if parse_date(date).is_some() => {}
+                    _ => anyhow::bail!("Invalid min/max for {} column {:?}", column.ty, column.name),
+                }
+            }
+        }
+        Ok(schema)
+    }
+}
+
+impl ColumnSchema {
+    /// What is wrong with `field`, if anything.
+    pub fn check(&self, field: &str) -> Option<String> {
+        if field.is_empty() {
+            return self.required.then(|| "required value is empty".to_string());
+        }
+        let Some(value) = parse_field(field, self.ty) else {
+            return Some(format!("{:?} is not a valid {}", field, self.ty));
+        };
+        if let Some(pattern) = &self.pattern {
+            if !pattern.is_match(field) {
+                return Some(format!("{:?} does not match /{}/", field, pattern));
+            }
+        }
+        if let Some(values) = &self.values {
+            if !values.iter().any(|v| v == field) {
+                return Some(format!("{:?} is not one of {}", field, values.join(", ")));
+            }
+        }
+        // parse_field normalizes dates to YYYY-MM-DD, which parse_date reads back
+        let out_of_range = |bound: &Bound, below: bool| match bound {
+            Bound::Number(n) => value.as_f64().is_some_and(|v| if below { v < *n } else { v > *n }),
+            Bound::Date(d) => {
+                let (v, d) = (value.as_str().and_then(parse_date), parse_date(d));
+                if below { v < d } else { v > d }
+            }
+        };
+        match (&self.min, &self.max) {
+            (Some(min), _) if out_of_range(min, true) => Some(format!("{:?} is less than {}", field, min)),
+            (_, Some(max)) if out_of_range(max, false) => Some(format!("{:?} is greater than {}", field, max)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bound::Number(n) => write!(f, "{}", n),
+            Bound::Date(d) => write!(f, "{}", d),
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.column.as_str() {
+            "" => write!(f, "row {}: {}", self.row, self.message),
+            column => write!(f, "row {}, column {:?}: {}", self.row, column, self.message),
+        }
+    }
+}
+
+fn string_type() -> ColumnType {
+    ColumnType::String
+}
+
+fn column_type<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<ColumnType, D::Error> {
+    let ty = String::deserialize(deserializer)?;
+    ty.parse().map_err(serde::de::Error::custom)
+}
+
+mod serde_regex {
+    use regex::Regex;
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Regex>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|pattern| Regex::new(&pattern).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    fn column(toml: &str) -> ColumnSchema {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_column_checks() {
+        let kit = column("name = \"Kit Number\"\ntype = \"int\"\nrequired = true\nmin = 1\nmax = 99");
+        assert_eq!(kit.check("10"), None);
+        assert_eq!(kit.check("").as_deref(), Some("required value is empty"));
+        assert_eq!(kit.check("ten").as_deref(), Some("\"ten\" is not a valid int"));
+        assert_eq!(kit.check("0").as_deref(), Some("\"0\" is less than 1"));
+        assert_eq!(kit.check("100").as_deref(), Some("\"100\" is greater than 99"));
+
+        let position = column("name = \"Position\"\nenum = [\"Goalkeeper\", \"Centre-Back\"]\npattern = \"^[A-Z]\"");
+        assert_eq!(position.check(""), None);
+        assert_eq!(position.check("Goalkeeper"), None);
+        assert!(position.check("goalkeeper").unwrap().contains("does not match"));
+        assert!(position.check("Striker").unwrap().contains("is not one of"));
+
+        let dob = column("name = \"DOB\"\ntype = \"date\"\nmin = \"1980-01-01\"");
+        assert_eq!(dob.check("Apr 18, 1990"), None);
+        assert!(dob.check("1978-01-28").unwrap().contains("less than"));
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        assert!(toml::from_str::<ColumnSchema>("name = \"a\"\ntype = \"integer\"").is_err());
+        assert!(toml::from_str::<ColumnSchema>("name = \"a\"\npattern = \"(\"").is_err());
+        assert!(toml::from_str::<ColumnSchema>("name = \"a\"\nrequird = true").is_err());
+    }
+
+    #[test]
+    fn test_validate_juventus() -> Result<()> {
+        let source = CsvSource {
+            input: "assets/juventus.csv".into(),
+            from: None,
+            dialect: Default::default(),
+            types: Default::default(),
+        };
+        assert_eq!(process_csv_validate(&source, "assets/juventus.schema.toml")?, vec![]);
+
+        let dialect = crate::cli::CsvDialect { columns: vec!["Player".into(), "Role".into()], ..Default::default() };
+        let violations = process_csv_validate(&CsvSource { dialect, ..source }, "assets/juventus.schema.toml")?;
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[1].to_string(), "row 0, column \"Position\": required column is missing");
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_malformed_rows() -> Result<()> {
+        let dir = TempDir::new("validate")?;
+        fs::write(dir.path("players.csv"), "Name,Kit Number\nBuffon,77\nChiellini,3,CB\nDybala,ten\n")?;
+        fs::write(dir.path("schema.toml"), "[[columns]]\nname = \"Kit Number\"\ntype = \"int\"\n")?;
+        let source = CsvSource { input: dir.path("players.csv"), from: None, dialect: Default::default(), types: Default::default() };
+        let violations: Vec<String> = process_csv_validate(&source, &dir.path("schema.toml"))?.iter().map(Violation::to_string).collect();
+        assert_eq!(violations, ["row 2: found 3 fields instead of 2", "row 3, column \"Kit Number\": \"ten\" is not a valid int"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,888 @@
+use std::{borrow::Cow, io::{self, BufWriter, Write}};
+use anyhow::Result;
+use serde_json::{Map, Value};
+
+use chrono::Datelike;
+use flate2::write::GzEncoder;
+use rust_xlsxwriter::{ExcelDateTime, Format, Workbook};
+
+use crate::{cli::{OutputOpts, Outputformat}, write_output};
+use super::{csv_convert::RecordStream, csv_encoding::EncodeWriter, csv_infer::parse_date};
+
+/// Serializes records one at a time so conversions run in constant memory.
+pub trait RecordWriter {
+    /// Columns typed as dates, for formats with a date type of their own.
+    fn set_dates(&mut self, _columns: &[String]) {}
+
+    fn write_header(&mut self, _headers: &[String]) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &Map<String, Value>) -> Result<()>;
+
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// Where records end up. Compressed outputs write their trailer in `finish_output`,
+/// so an error there is reported rather than lost when the encoder is dropped.
+pub trait OutputWrite: Write {
+    fn finish_output(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+/// An output file or stdout, gzip or zstd compressed or as is.
+pub enum CompressWriter {
+    Plain(Box<dyn Write>),
+    Gzip(GzEncoder<Box<dyn Write>>),
+    Zstd(zstd::Encoder<'static, Box<dyn Write>>),
+}
+
+pub struct JsonArrayWriter<W: OutputWrite> {
+    writer: W,
+    count: usize,
+}
+
+pub struct NdjsonWriter<W: OutputWrite> {
+    writer: W,
+}
+
+pub struct YamlWriter<W: OutputWrite> {
+    writer: W,
+    count: usize,
+}
+
+pub struct TomlWriter<W: OutputWrite> {
+    writer: W,
+    table: String,
+    count: usize,
+}
+
+pub struct XmlWriter<W: OutputWrite> {
+    writer: W,
+    root: String,
+    row: String,
+    started: bool,
+}
+
+pub struct MarkdownWriter<W: OutputWrite> {
+    writer: W,
+    headers: Vec<String>,
+}
+
+pub struct HtmlWriter<W: OutputWrite> {
+    writer: W,
+    headers: Vec<String>,
+    started: bool,
+}
+
+pub struct CsvWriter<W: OutputWrite> {
+    // taken back on `finish` to finish the output under it
+    writer: Option<csv::Writer<W>>,
+    headers: Vec<String>,
+}
+
+/// An xlsx workbook with one sheet, built in memory and written on `finish`.
+pub struct XlsxWriter<W: OutputWrite> {
+    writer: W,
+    workbook: Workbook,
+    headers: Vec<String>,
+    dates: Vec<String>,
+    row: u32,
+}
+
+/// Records nested under the values of their key columns, like `{"Goalkeeper": {"count": 4}}`.
+/// The document is written at once on `finish`.
+pub struct KeyedWriter<W: OutputWrite> {
+    writer: W,
+    format: Outputformat,
+    keys: Vec<String>,
+    root: Map<String, Value>,
+}
+
+pub fn record_writer<'a, W: OutputWrite + 'a>(opts: &OutputOpts, writer: W) -> Box<dyn RecordWriter + 'a> {
+    match opts.format(None) {
+        Outputformat::Json => Box::new(JsonArrayWriter::new(writer)),
+        Outputformat::Ndjson => Box::new(NdjsonWriter::new(writer)),
+        Outputformat::Yaml => Box::new(YamlWriter::new(writer)),
+        Outputformat::Toml => Box::new(TomlWriter::new(writer, &opts.root)),
+        Outputformat::Xml => Box::new(XmlWriter::new(writer, &opts.root, &opts.row)),
+        Outputformat::Markdown => Box::new(MarkdownWriter::new(writer)),
+        Outputformat::Html => Box::new(HtmlWriter::new(writer)),
+        Outputformat::Csv => Box::new(CsvWriter::new(writer, opts.out_delimiter.unwrap_or(b','))),
+        Outputformat::Xlsx => Box::new(XlsxWriter::new(writer)),
+    }
+}
+
+/// A writer nesting records by `keys`, which only JSON, YAML and TOML can express.
+pub fn keyed_writer<'a, W: OutputWrite + 'a>(opts: &OutputOpts, keys: &[String], writer: W) -> Result<Box<dyn RecordWriter + 'a>> {
+    if keys.is_empty() {
+        anyhow::bail!("--keyed needs --group-by columns to key records by");
+    }
+    match opts.format(None) {
+        format @ (Outputformat::Json | Outputformat::Yaml | Outputformat::Toml) => Ok(Box::new(KeyedWriter {
+            writer,
+            format,
+            keys: keys.to_vec(),
+            root: Map::new(),
+        })),
+        format => Err(anyhow::anyhow!("--keyed needs json, yaml or toml output, not {}", format)),
+    }
+}
+
+/// Write `stream` to `output` in the format `out` asks for or the output name implies.
+pub fn write_stream(stream: RecordStream<'_>, output: &str, out: &OutputOpts) -> Result<()> {
+    let out = OutputOpts { format: Some(out.format(Some(output))), ..out.clone() };
+    let mut writer = record_writer(&out, BufWriter::new(output_writer(output, &out)?));
+    writer.set_dates(&stream.dates);
+    writer.write_header(&stream.headers)?;
+    for record in stream.records {
+        writer.write_record(&record?)?;
+    }
+    writer.finish()
+}
+
+/// `write_stream`, CSV unless asked otherwise, for commands whose input is usually a CSV file.
+pub fn write_csv_stream(stream: RecordStream<'_>, output: &str, out: &OutputOpts) -> Result<()> {
+    let format = out.format.or_else(|| Outputformat::from_path(output)).unwrap_or(Outputformat::Csv);
+    write_stream(stream, output, &OutputOpts { format: Some(format), ..out.clone() })
+}
+
+/// Open `output` (`-` for stdout), compressed when asked for or named `.gz` / `.zst`
+/// and transcoded to `--output-encoding`.
+pub fn output_writer(output: &str, opts: &OutputOpts) -> Result<Box<dyn OutputWrite>> {
+    let writer = write_output(output)?;
+    let output = output.to_ascii_lowercase();
+    let writer = if opts.gzip || output.ends_with(".gz") {
+        CompressWriter::Gzip(GzEncoder::new(writer, flate2::Compression::default()))
+    } else if opts.zstd || output.ends_with(".zst") {
+        CompressWriter::Zstd(zstd::Encoder::new(writer, 0)?)
+    } else {
+        CompressWriter::Plain(writer)
+    };
+    match opts.output_encoding {
+        Some(encoding) if encoding != encoding_rs::UTF_8 => {
+            if opts.format(None) != Outputformat::Csv {
+                anyhow::bail!("--output-encoding only applies to csv output");
+            }
+            Ok(Box::new(EncodeWriter::new(writer, encoding)))
+        }
+        _ => Ok(Box::new(writer)),
+    }
+}
+
+/// Plain text of a value for table cells: strings unquoted, null empty,
+/// anything nested as compact JSON.
+pub fn cell_text(value: &Value) -> Cow<'_, str> {
+    match value {
+        Value::String(s) => Cow::Borrowed(s),
+        Value::Null => Cow::Borrowed(""),
+        _ => Cow::Owned(value.to_string()),
+    }
+}
+
+/// Formats with a fixed set of columns would drop the fields `headers` doesn't name,
+/// like those of a `--flexible` row wider than the sampled ones, so fail instead.
+fn check_columns(headers: &[String], record: &Map<String, Value>) -> Result<()> {
+    match record.keys().find(|key| !headers.contains(key)) {
+        Some(key) => anyhow::bail!(
+            "Field {:?} isn't one of the {} output columns, which come from the first rows: \
+             raise --sample-rows to cover the wider --flexible rows or pick json, ndjson or yaml output",
+            key,
+            headers.len()
+        ),
+        None => Ok(()),
+    }
+}
+
+impl<W: OutputWrite> JsonArrayWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: OutputWrite> RecordWriter for JsonArrayWriter<W> {
+    fn write_record(&mut self, record: &Map<String, Value>) -> Result<()> {
+        let separator = if self.count == 0 { "[\n" } else { ",\n" };
+        self.writer.write_all(separator.as_bytes())?;
+        // same layout as pretty printing the whole array at once
+        let pretty = serde_json::to_string_pretty(record)?;
+        for (i, line) in pretty.lines().enumerate() {
+            if i > 0 {
+                self.writer.write_all(b"\n")?;
+            }
+            write!(self.writer, "  {}", line)?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let end = if self.count == 0 { "[]" } else { "\n]" };
+        self.writer.write_all(end.as_bytes())?;
+        self.writer.finish_output()?;
+        Ok(())
+    }
+}
+
+impl<W: OutputWrite> NdjsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: OutputWrite> RecordWriter for NdjsonWriter<W> {
+    fn write_record(&mut self, record: &Map<String, Value>) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.finish_output()?;
+        Ok(())
+    }
+}
+
+impl<W: OutputWrite> YamlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: OutputWrite> RecordWriter for YamlWriter<W> {
+    fn write_record(&mut self, record: &Map<String, Value>) -> Result<()> {
+        // a one item sequence per record, back to back they form the whole sequence
+        // through a string, so write errors stay io errors
+        self.writer.write_all(serde_yaml::to_string(&[record])?.as_bytes())?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.count == 0 {
+            self.writer.write_all(b"[]\n")?;
+        }
+        self.writer.finish_output()?;
+        Ok(())
+    }
+}
+
+impl<W: OutputWrite> TomlWriter<W> {
+    pub fn new(writer: W, table: &str) -> Self {
+        Self { writer, table: table.to_string(), count: 0 }
+    }
+}
+
+impl<W: OutputWrite> RecordWriter for TomlWriter<W> {
+    fn write_record(&mut self, record: &Map<String, Value>) -> Result<()> {
+        // TOML has no null, missing keys are the closest thing
+        let mut doc = Map::new();
+        doc.insert(self.table.clone(), Value::Array(vec![strip_nulls(record)]));
+        if self.count > 0 {
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.write_all(toml::to_string(&doc)?.as_bytes())?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.finish_output()?;
+        Ok(())
+    }
+}
+
+impl<W: OutputWrite> RecordWriter for KeyedWriter<W> {
+    fn write_record(&mut self, record: &Map<String, Value>) -> Result<()> {
+        let mut record = record.clone();
+        let mut node = &mut self.root;
+        for (i, key) in self.keys.iter().enumerate() {
+            let key = cell_text(&record.shift_remove(key).unwrap_or(Value::Null)).into_owned();
+            if i + 1 == self.keys.len() {
+                node.insert(key, Value::Object(record));
+                break;
+            }
+            let child = node.entry(key).or_insert_with(|| Value::Object(Map::new()));
+            node = match child {
+                Value::Object(child) => child,
+                _ => unreachable!("all records are keyed by the same columns"),
+            };
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let doc = match self.format {
+            Outputformat::Yaml => serde_yaml::to_string(&self.root)?,
+            Outputformat::Toml => toml::to_string(&strip_nulls(&self.root))?,
+            _ => serde_json::to_string_pretty(&self.root)?,
+        };
+        self.writer.write_all(doc.as_bytes())?;
+        self.writer.finish_output()?;
+        Ok(())
+    }
+}
+
+fn strip_nulls(record: &Map<String, Value>) -> Value {
+    let map = record
+        .iter()
+        .filter(|(_, v)| !v.is_null())
+        .map(|(k, v)| {
+            let v = match v {
+                Value::Object(o) => strip_nulls(o),
+                Value::Array(a) => Value::Array(a.iter().filter(|v| !v.is_null()).cloned().collect()),
+                _ => v.clone(),
+            };
+            (k.clone(), v)
+        })
+        .collect();
+    Value::Object(map)
+}
+
+impl<W: OutputWrite> XmlWriter<W> {
+    pub fn new(writer: W, root: &str, row: &str) -> Self {
+        Self {
+            writer,
+            root: xml_name(root),
+            row: xml_name(row),
+            started: false,
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if !self.started {
+            writeln!(self.writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+            writeln!(self.writer, "<{}>", self.root)?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    fn write_element(&mut self, name: &str, value: &Value, depth: usize) -> Result<()> {
+        let indent = "  ".repeat(depth);
+        match value {
+            Value::Null => writeln!(self.writer, "{}<{}/>", indent, name)?,
+            Value::Array(items) => {
+                for item in items {
+                    self.write_element(name, item, depth)?;
+                }
+            }
+            Value::Object(map) => {
+                writeln!(self.writer, "{}<{}>", indent, name)?;
+                for (k, v) in map {
+                    self.write_element(&xml_name(k), v, depth + 1)?;
+                }
+                writeln!(self.writer, "{}</{}>", indent, name)?;
+            }
+            _ => writeln!(self.writer, "{}<{}>{}</{}>", indent, name, escape_markup(&cell_text(value)), name)?,
+        }
+        Ok(())
+    }
+}
+
+impl<W: OutputWrite> RecordWriter for XmlWriter<W> {
+    fn write_record(&mut self, record: &Map<String, Value>) -> Result<()> {
+        self.start()?;
+        writeln!(self.writer, "  <{}>", self.row)?;
+        for (k, v) in record {
+            self.write_element(&xml_name(k), v, 2)?;
+        }
+        writeln!(self.writer, "  </{}>", self.row)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.start()?;
+        writeln!(self.writer, "</{}>", self.root)?;
+        self.writer.finish_output()?;
+        Ok(())
+    }
+}
+
+/// Turn a column name into a valid XML element name, `Kit Number` becomes `Kit_Number`.
+fn xml_name(name: &str) -> String {
+    let mut ret: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '_' | '-' | '.') { c } else { '_' })
+        .collect();
+    if !ret.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        ret.insert(0, '_');
+    }
+    ret
+}
+
+pub fn escape_markup(s: &str) -> Cow<'_, str> {
+    if !s.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(s);
+    }
+    let mut ret = String::with_capacity(s.len() + 8);
+    for c in s.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&#39;"),
+            _ => ret.push(c),
+        }
+    }
+    Cow::Owned(ret)
+}
+
+impl<W: OutputWrite> MarkdownWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, headers: Vec::new() }
+    }
+}
+
+impl<W: OutputWrite> RecordWriter for MarkdownWriter<W> {
+    fn write_header(&mut self, headers: &[String]) -> Result<()> {
+        self.headers = headers.to_vec();
+        let cells: Vec<_> = headers.iter().map(|h| escape_markdown(h)).collect();
+        writeln!(self.writer, "| {} |", cells.join(" | "))?;
+        writeln!(self.writer, "|{}", " --- |".repeat(headers.len()))?;
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &Map<String, Value>) -> Result<()> {
+        check_columns(&self.headers, record)?;
+        let cells: Vec<_> = self
+            .headers
+            .iter()
+            .map(|h| escape_markdown(&record.get(h).map(cell_text).unwrap_or_default()))
+            .collect();
+        writeln!(self.writer, "| {} |", cells.join(" | "))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.finish_output()?;
+        Ok(())
+    }
+}
+
+fn escape_markdown(s: &str) -> String {
+    s.replace('|', "\\|").replace("\r\n", "<br>").replace('\n', "<br>")
+}
+
+impl<W: OutputWrite> HtmlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, headers: Vec::new(), started: false }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if self.started {
+            return Ok(());
+        }
+        self.writer.write_all(HTML_HEAD.as_bytes())?;
+        writeln!(self.writer, "<table>\n<thead>\n<tr>")?;
+        for h in &self.headers {
+            writeln!(self.writer, "<th>{}</th>", escape_markup(h))?;
+        }
+        writeln!(self.writer, "</tr>\n</thead>\n<tbody>")?;
+        self.started = true;
+        Ok(())
+    }
+}
+
+const HTML_HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<style>
+table { border-collapse: collapse; font-family: sans-serif; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }
+thead { background: #f0f0f0; }
+</style>
+</head>
+<body>
+"#;
+
+impl<W: OutputWrite> RecordWriter for HtmlWriter<W> {
+    fn write_header(&mut self, headers: &[String]) -> Result<()> {
+        self.headers = headers.to_vec();
+        self.start()
+    }
+
+    fn write_record(&mut self, record: &Map<String, Value>) -> Result<()> {
+        check_columns(&self.headers, record)?;
+        self.start()?;
+        self.writer.write_all(b"<tr>")?;
+        for h in &self.headers {
+            let text = record.get(h).map(cell_text).unwrap_or_default();
+            write!(self.writer, "<td>{}</td>", escape_markup(&text))?;
+        }
+        self.writer.write_all(b"</tr>\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.start()?;
+        writeln!(self.writer, "</tbody>\n</table>\n</body>\n</html>")?;
+        self.writer.finish_output()?;
+        Ok(())
+    }
+}
+
+impl<W: OutputWrite> XlsxWriter<W> {
+    pub fn new(writer: W) -> Self {
+        let mut workbook = Workbook::new();
+        workbook.add_worksheet();
+        Self { writer, workbook, headers: Vec::new(), dates: Vec::new(), row: 0 }
+    }
+}
+
+impl<W: OutputWrite> RecordWriter for XlsxWriter<W> {
+    fn set_dates(&mut self, columns: &[String]) {
+        self.dates = columns.to_vec();
+    }
+
+    fn write_header(&mut self, headers: &[String]) -> Result<()> {
+        self.headers = headers.to_vec();
+        let bold = Format::new().set_bold();
+        let sheet = self.workbook.worksheet_from_index(0)?;
+        for (col, header) in headers.iter().enumerate() {
+            sheet.write_string_with_format(0, col as u16, header, &bold)?;
+        }
+        sheet.set_freeze_panes(1, 0)?;
+        self.row = 1;
+        Ok(())
+    }
+
+    /// Numbers, booleans and the dates of date columns get typed cells, everything else
+    /// is text. Excel has no dates before 1900, those stay text too.
+    fn write_record(&mut self, record: &Map<String, Value>) -> Result<()> {
+        check_columns(&self.headers, record)?;
+        let date_format = Format::new().set_num_format("yyyy-mm-dd");
+        let sheet = self.workbook.worksheet_from_index(0)?;
+        for (col, header) in self.headers.iter().enumerate() {
+            let col = col as u16;
+            let date = |s: &str| {
+                let date = parse_date(s).filter(|_| self.dates.contains(header))?;
+                ExcelDateTime::from_ymd(u16::try_from(date.year()).ok()?, date.month() as u8, date.day() as u8).ok()
+            };
+            match record.get(header).unwrap_or(&Value::Null) {
+                Value::Null => {}
+                Value::Number(n) => {
+                    sheet.write_number(self.row, col, n.as_f64().unwrap_or_default())?;
+                }
+                Value::Bool(b) => {
+                    sheet.write_boolean(self.row, col, *b)?;
+                }
+                Value::String(s) => match date(s) {
+                    Some(date) => {
+                        sheet.write_datetime_with_format(self.row, col, &date, &date_format)?;
+                    }
+                    None => {
+                        sheet.write_string(self.row, col, s)?;
+                    }
+                },
+                value => {
+                    sheet.write_string(self.row, col, value.to_string())?;
+                }
+            }
+        }
+        self.row += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.workbook.worksheet_from_index(0)?.autofit();
+        self.writer.write_all(&self.workbook.save_to_buffer()?)?;
+        self.writer.finish_output()?;
+        Ok(())
+    }
+}
+
+impl<W: OutputWrite> CsvWriter<W> {
+    pub fn new(writer: W, delimiter: u8) -> Self {
+        let writer = csv::WriterBuilder::new().delimiter(delimiter).from_writer(writer);
+        Self { writer: Some(writer), headers: Vec::new() }
+    }
+
+    fn writer(&mut self) -> Result<&mut csv::Writer<W>> {
+        self.writer.as_mut().ok_or_else(|| anyhow::anyhow!("The csv output is already finished"))
+    }
+}
+
+impl<W: OutputWrite> RecordWriter for CsvWriter<W> {
+    fn write_header(&mut self, headers: &[String]) -> Result<()> {
+        self.headers = headers.to_vec();
+        self.writer()?.write_record(headers)?;
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &Map<String, Value>) -> Result<()> {
+        check_columns(&self.headers, record)?;
+        let fields: Vec<String> = self
+            .headers
+            .iter()
+            .map(|h| record.get(h).map(cell_text).unwrap_or_default().into_owned())
+            .collect();
+        self.writer()?.write_record(fields)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.into_inner().map_err(|e| e.into_error())?.finish_output()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for CompressWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressWriter::Plain(writer) => writer.write(buf),
+            CompressWriter::Gzip(writer) => writer.write(buf),
+            CompressWriter::Zstd(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressWriter::Plain(writer) => writer.flush(),
+            CompressWriter::Gzip(writer) => writer.flush(),
+            CompressWriter::Zstd(writer) => writer.flush(),
+        }
+    }
+}
+
+impl OutputWrite for CompressWriter {
+    fn finish_output(&mut self) -> io::Result<()> {
+        match self {
+            CompressWriter::Plain(writer) => writer.flush(),
+            CompressWriter::Gzip(writer) => {
+                writer.try_finish()?;
+                writer.get_mut().flush()
+            }
+            CompressWriter::Zstd(writer) => {
+                writer.do_finish()?;
+                writer.get_mut().flush()
+            }
+        }
+    }
+}
+
+impl OutputWrite for Vec<u8> {}
+
+impl<W: OutputWrite + ?Sized> OutputWrite for &mut W {
+    fn finish_output(&mut self) -> io::Result<()> {
+        (**self).finish_output()
+    }
+}
+
+impl<W: OutputWrite + ?Sized> OutputWrite for Box<W> {
+    fn finish_output(&mut self) -> io::Result<()> {
+        (**self).finish_output()
+    }
+}
+
+impl<W: OutputWrite> OutputWrite for BufWriter<W> {
+    fn finish_output(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.get_mut().finish_output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn records() -> Vec<Map<String, Value>> {
+        let rows = json!([
+            {"Name": "Buffon", "Kit Number": 77, "Nested": {"a": [1, 2]}},
+            {"Name": "Dybala", "Kit Number": null, "Nested": {}},
+        ]);
+        rows.as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r.as_object().unwrap().clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_keyed_writer() -> Result<()> {
+        let records = [
+            json!({"Position": "Goalkeeper", "Nationality": "Italy", "count": 3}),
+            json!({"Position": "Goalkeeper", "Nationality": "Poland", "count": 1}),
+            json!({"Position": "Centre-Back", "Nationality": "Italy", "count": 2}),
+        ];
+        let keys = ["Position".to_string(), "Nationality".to_string()];
+        let write = |format| -> Result<String> {
+            let mut buf = Vec::new();
+            let opts = OutputOpts { format: Some(format), ..Default::default() };
+            let mut writer = keyed_writer(&opts, &keys, &mut buf)?;
+            for record in &records {
+                writer.write_record(record.as_object().unwrap())?;
+            }
+            writer.finish()?;
+            drop(writer);
+            Ok(String::from_utf8(buf)?)
+        };
+        let ret: Value = serde_json::from_str(&write(Outputformat::Json)?)?;
+        assert_eq!(
+            ret,
+            json!({"Goalkeeper": {"Italy": {"count": 3}, "Poland": {"count": 1}}, "Centre-Back": {"Italy": {"count": 2}}})
+        );
+        assert!(write(Outputformat::Toml)?.contains("[Goalkeeper.Italy]\ncount = 3"));
+        assert!(write(Outputformat::Csv).is_err());
+        Ok(())
+    }
+
+    fn write_all(format: Outputformat, records: &[Map<String, Value>]) -> Result<String> {
+        let opts = OutputOpts { format: Some(format), ..Default::default() };
+        let headers: Vec<String> = records.first().map(|r| r.keys().cloned().collect()).unwrap_or_default();
+        let mut buf = Vec::new();
+        let mut writer = record_writer(&opts, &mut buf);
+        writer.write_header(&headers)?;
+        for record in records {
+            writer.write_record(record)?;
+        }
+        writer.finish()?;
+        drop(writer);
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_streaming_json_matches_pretty() -> Result<()> {
+        let records = records();
+        assert_eq!(write_all(Outputformat::Json, &records)?, serde_json::to_string_pretty(&records)?);
+        assert_eq!(write_all(Outputformat::Json, &[])?, "[]");
+        Ok(())
+    }
+
+    #[test]
+    fn test_streaming_yaml_matches_document() -> Result<()> {
+        let records = records();
+        assert_eq!(write_all(Outputformat::Yaml, &records)?, serde_yaml::to_string(&records)?);
+        assert_eq!(write_all(Outputformat::Yaml, &[])?, "[]\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_ndjson() -> Result<()> {
+        let ret = write_all(Outputformat::Ndjson, &records())?;
+        let lines: Vec<_> = ret.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], r#"{"Name":"Buffon","Kit Number":77,"Nested":{"a":[1,2]}}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn test_toml() -> Result<()> {
+        let ret = write_all(Outputformat::Toml, &records())?;
+        let doc: toml::Table = toml::from_str(&ret)?;
+        let rows = doc["rows"].as_array().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["Kit Number"].as_integer(), Some(77));
+        assert!(rows[1].get("Kit Number").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_xml() -> Result<()> {
+        let ret = write_all(Outputformat::Xml, &records()[..1])?;
+        assert_eq!(
+            ret,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<rows>
+  <row>
+    <Name>Buffon</Name>
+    <Kit_Number>77</Kit_Number>
+    <Nested>
+      <a>1</a>
+      <a>2</a>
+    </Nested>
+  </row>
+</rows>
+"#
+        );
+        assert_eq!(xml_name("1st"), "_1st");
+        assert_eq!(escape_markup("<a & 'b'>"), "&lt;a &amp; &#39;b&#39;&gt;");
+        Ok(())
+    }
+
+    #[test]
+    fn test_markdown() -> Result<()> {
+        let mut records = records();
+        records[1].insert("Name".into(), json!("a|b"));
+        let ret = write_all(Outputformat::Markdown, &records)?;
+        let lines: Vec<_> = ret.lines().collect();
+        assert_eq!(lines[0], "| Name | Kit Number | Nested |");
+        assert_eq!(lines[1], "| --- | --- | --- |");
+        assert_eq!(lines[2], r#"| Buffon | 77 | {"a":[1,2]} |"#);
+        assert_eq!(lines[3], r"| a\|b |  | {} |");
+        Ok(())
+    }
+
+    #[test]
+    fn test_html() -> Result<()> {
+        let ret = write_all(Outputformat::Html, &records())?;
+        assert!(ret.starts_with("<!DOCTYPE html>"));
+        assert!(ret.contains("<th>Kit Number</th>"));
+        assert!(ret.contains("<tr><td>Buffon</td><td>77</td>"));
+        assert!(ret.trim_end().ends_with("</html>"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv() -> Result<()> {
+        let mut records = records();
+        records[1].insert("Name".into(), json!("Dybala, Paulo"));
+        let opts = OutputOpts { format: Some(Outputformat::Csv), out_delimiter: Some(b';'), ..Default::default() };
+        let mut buf = Vec::new();
+        let mut writer = record_writer(&opts, &mut buf);
+        writer.write_header(&["Name".to_string(), "Kit Number".to_string()])?;
+        for record in &mut records {
+            // a field beyond the columns fails rather than going missing from the output
+            assert!(writer.write_record(record).is_err());
+            record.remove("Nested");
+            writer.write_record(record)?;
+        }
+        writer.finish()?;
+        drop(writer);
+        assert_eq!(String::from_utf8(buf)?, "Name;Kit Number\nBuffon;77\nDybala, Paulo;\n");
+        Ok(())
+    }
+
+    /// A buffer that stays readable once the writer owning it is done.
+    #[derive(Clone, Default)]
+    struct SharedBuf(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_compressed_finish() -> Result<()> {
+        let (gzip, zstd) = (SharedBuf::default(), SharedBuf::default());
+        let boxed = |buf: &SharedBuf| -> Box<dyn Write> { Box::new(buf.clone()) };
+        let outputs = [
+            CompressWriter::Gzip(GzEncoder::new(boxed(&gzip), flate2::Compression::default())),
+            CompressWriter::Zstd(zstd::Encoder::new(boxed(&zstd), 0)?),
+        ];
+        // the writers are still alive, the trailers must be written by now
+        let mut writers = Vec::new();
+        for output in outputs {
+            let mut writer = NdjsonWriter::new(BufWriter::new(output));
+            writer.write_record(json!({"Name": "Buffon"}).as_object().unwrap())?;
+            writer.finish()?;
+            writers.push(writer);
+        }
+        let mut text = String::new();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&gzip.0.borrow()[..]), &mut text)?;
+        assert_eq!(text, "{\"Name\":\"Buffon\"}\n");
+        assert_eq!(zstd::decode_all(&zstd.0.borrow()[..])?, text.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use std::io::{Cursor, Read};
+use anyhow::{Context, Result};
+use calamine::{open_workbook_from_rs, Data, Reader, Xlsx};
+use serde_json::Value;
+
+use crate::cli::{CsvDialect, CsvTypes};
+use super::{
+    csv_convert::{extend_headers, Record, RecordStream},
+    csv_writer::cell_text,
+};
+
+/// Records of the `--sheet` of an xlsx workbook, the first sheet by default. Cells keep
+/// their spreadsheet type, or are all text with `--no-infer`.
+pub fn xlsx_records<'a>(mut reader: Box<dyn Read>, dialect: &CsvDialect, types: &CsvTypes) -> Result<RecordStream<'a>> {
+    // the zip archive needs seeking
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    let mut workbook: Xlsx<_> = open_workbook_from_rs(Cursor::new(buf)).context("Invalid xlsx file")?;
+    let names = workbook.sheet_names();
+    let sheet = match &dialect.sheet {
+        Some(sheet) if names.contains(sheet) => sheet.clone(),
+        Some(sheet) => anyhow::bail!("No sheet {:?}, the workbook has {}", sheet, names.join(", ")),
+        None => names.first().cloned().ok_or_else(|| anyhow::anyhow!("The workbook has no sheets"))?,
+    };
+    let range = workbook.worksheet_range(&sheet).with_context(|| format!("Cannot read sheet {:?}", sheet))?;
+
+    let mut rows = range.rows();
+    let first: Vec<String> = match dialect.header {
+        true => rows.next().unwrap_or_default().iter().map(|cell| cell.to_string()).collect(),
+        false => (0..range.width()).map(|_| String::new()).collect(),
+    };
+    let mut headers = dialect.column_names(first);
+    let records: Vec<Result<Record>> = rows
+        .map(|row| {
+            extend_headers(&mut headers, row.len());
+            let record = headers
+                .iter()
+                .zip(row)
+                .map(|(header, cell)| {
+                    let value = cell_value(cell);
+                    let value = match types.infer {
+                        true => value,
+                        false => Value::String(cell_text(&value).into_owned()),
+                    };
+                    (header.clone(), value)
+                })
+                .collect();
+            Ok(record)
+        })
+        .collect();
+    // columns with date cells are dates, when they are typed at all
+    let skip = dialect.header as usize;
+    let dates = headers
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| types.infer && range.rows().skip(skip).any(|row| matches!(row.get(*i), Some(Data::DateTime(dt)) if dt.is_datetime())))
+        .map(|(_, header)| header.clone())
+        .collect();
+    Ok(RecordStream { headers, dates, records: Box::new(records.into_iter()) })
+}
+
+/// Whole numbers are ints, since spreadsheets store every number as a float. Dates
+/// become `YYYY-MM-DD`, with the time when there is one.
+fn cell_value(cell: &Data) -> Value {
+    match cell {
+        Data::Empty => Value::Null,
+        Data::Int(i) => Value::from(*i),
+        Data::Float(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => Value::from(*f as i64),
+        Data::Float(f) => Value::from(*f),
+        Data::Bool(b) => Value::Bool(*b),
+        Data::String(s) | Data::DateTimeIso(s) | Data::DurationIso(s) => Value::String(s.clone()),
+        Data::DateTime(dt) if dt.is_datetime() => {
+            let (y, m, d, hh, mm, ss, _) = dt.to_ymd_hms_milli();
+            match (hh, mm, ss) {
+                (0, 0, 0) => Value::String(format!("{:04}-{:02}-{:02}", y, m, d)),
+                _ => Value::String(format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", y, m, d, hh, mm, ss)),
+            }
+        }
+        Data::DateTime(dt) => Value::from(dt.as_f64()),
+        Data::Error(e) => Value::String(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::csv_writer::{RecordWriter, XlsxWriter};
+    use serde_json::json;
+
+    fn workbook() -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        let mut writer = XlsxWriter::new(&mut buf);
+        writer.set_dates(&["DOB".into()]);
+        writer.write_header(&["Name".into(), "DOB".into(), "Kit Number".into(), "Captain".into()])?;
+        writer.write_record(json!({"Name": "Buffon", "DOB": "1978-01-28", "Kit Number": 77, "Captain": false}).as_object().unwrap())?;
+        writer.write_record(json!({"Name": "Chiellini", "DOB": null, "Kit Number": 3.5, "Captain": true}).as_object().unwrap())?;
+        writer.finish()?;
+        Ok(buf)
+    }
+
+    fn read(dialect: CsvDialect, types: CsvTypes) -> Result<(Vec<String>, Vec<Record>)> {
+        let stream = xlsx_records(Box::new(Cursor::new(workbook()?)), &dialect, &types)?;
+        Ok((stream.headers, stream.records.collect::<Result<_>>()?))
+    }
+
+    #[test]
+    fn test_xlsx_round_trip() -> Result<()> {
+        let (headers, records) = read(Default::default(), Default::default())?;
+        assert_eq!(headers, vec!["Name", "DOB", "Kit Number", "Captain"]);
+        assert_eq!(Value::Object(records[0].clone()), json!({"Name": "Buffon", "DOB": "1978-01-28", "Kit Number": 77, "Captain": false}));
+        assert_eq!(Value::Object(records[1].clone()), json!({"Name": "Chiellini", "DOB": null, "Kit Number": 3.5, "Captain": true}));
+
+        let (_, records) = read(Default::default(), CsvTypes { infer: false, ..Default::default() })?;
+        assert_eq!(records[0]["Kit Number"], json!("77"));
+
+        let (headers, records) = read(CsvDialect { header: false, ..Default::default() }, Default::default())?;
+        assert_eq!(headers[0], "col_1");
+        assert_eq!(records.len(), 3);
+
+        assert!(read(CsvDialect { sheet: Some("Roster".into()), ..Default::default() }, Default::default()).is_err());
+        let (_, records) = read(CsvDialect { sheet: Some("Sheet1".into()), ..Default::default() }, Default::default())?;
+        assert_eq!(records.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_xlsx_dates() -> Result<()> {
+        let mut buf = Vec::new();
+        let mut writer = XlsxWriter::new(&mut buf);
+        writer.set_dates(&["DOB".into()]);
+        writer.write_header(&["Name".into(), "DOB".into(), "Note".into()])?;
+        writer.write_record(json!({"Name": "Buffon", "DOB": "1978-01-28", "Note": "2001-07-01"}).as_object().unwrap())?;
+        writer.write_record(json!({"Name": "Cavour", "DOB": "1810-08-10", "Note": "unknown"}).as_object().unwrap())?;
+        writer.finish()?;
+
+        let stream = xlsx_records(Box::new(Cursor::new(buf)), &Default::default(), &Default::default())?;
+        assert_eq!(stream.dates, vec!["DOB"]);
+        let records = stream.records.collect::<Result<Vec<_>>>()?;
+        assert_eq!(records[0]["Note"], json!("2001-07-01"));
+        assert_eq!(records[1]["DOB"], json!("1810-08-10"));
+        Ok(())
+    }
+}
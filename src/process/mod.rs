@@ -1,4 +1,26 @@
 mod csv_convert;
+mod csv_crypt;
+mod csv_diff;
+mod csv_fake;
+mod csv_encoding;
+mod csv_filter;
+mod csv_fixed;
+mod csv_group;
+mod csv_infer;
+mod csv_mapping;
+mod csv_mask;
+mod csv_nested;
+mod csv_parallel;
+mod csv_progress;
+mod csv_query;
+mod csv_reject;
+mod csv_show;
+mod csv_split;
+mod csv_stats;
+mod csv_structured;
+mod csv_validate;
+mod csv_writer;
+mod csv_xlsx;
 mod gen_pass;
 mod b64;
 mod text;
@@ -6,6 +28,16 @@ mod http_serve;
 mod jwt;
 
 pub use csv_convert::process_csv;
+pub use csv_crypt::{process_csv_decrypt, process_csv_encrypt};
+pub use csv_diff::process_csv_diff;
+pub use csv_fake::process_csv_fake;
+pub use csv_filter::Expr;
+pub use csv_mask::process_csv_mask;
+pub use csv_query::process_csv_query;
+pub use csv_show::process_csv_show;
+pub use csv_split::{process_csv_merge, process_csv_split};
+pub use csv_stats::process_csv_stats;
+pub use csv_validate::process_csv_validate;
 pub use gen_pass::process_genpass;  
 pub use b64::{process_encode, process_decode};
 pub use text::{process_text_sign, process_text_verify, process_generate, process_encrypt, process_decrypt};
@@ -4,7 +4,7 @@ use anyhow::Result;
 use base64::{prelude::BASE64_URL_SAFE_NO_PAD as URL_SAFE_NO_PAD, Engine};
 use chacha20poly1305::{
     aead::{Aead, AeadCore, KeyInit, OsRng},
-    ChaCha20Poly1305
+    ChaCha20Poly1305, Nonce
 };
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 
@@ -89,13 +89,10 @@ pub fn process_encrypt(input: &str, key: &str, output: &str) -> Result<()> {
     let mut reader = read_input(input)?;
     let mut buf = Vec::new();
     reader.read_to_end(&mut buf)?;
-    let key = fs::read(key)?;
-    let key = chacha20poly1305::Key::from_slice(&key);
-    let cipher = ChaCha20Poly1305::new(key);
-    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
-    let ciphertext = cipher.encrypt(&nonce, buf.as_ref()).map_err(|e| anyhow::anyhow!(e))?;
+    let cipher = load_cipher(key)?;
+    let (nonce, ciphertext) = seal(&cipher, &buf)?;
 
-    let encoded_nonce = URL_SAFE_NO_PAD.encode(&nonce);
+    let encoded_nonce = URL_SAFE_NO_PAD.encode(nonce);
     let encoded_ciphertext = URL_SAFE_NO_PAD.encode(&ciphertext);
 
     match output {
@@ -116,14 +113,11 @@ pub fn process_decrypt(input: &str, key: &str, output: &str) -> Result<()> {
     let mut reader = read_input(input)?;
     let mut buf = String::new();
     reader.read_to_string(&mut buf)?;
-    let key = fs::read(key)?;
-    let key = chacha20poly1305::Key::from_slice(&key);
-    let cipher = ChaCha20Poly1305::new(key);
+    let cipher = load_cipher(key)?;
     let mut lines = buf.lines();
     let nonce = URL_SAFE_NO_PAD.decode(lines.next().unwrap().strip_prefix("nonce: ").unwrap())?;
     let ciphertext = URL_SAFE_NO_PAD.decode(lines.next().unwrap().strip_prefix("ciphertext: ").unwrap())?;
-    let nonce = chacha20poly1305::Nonce::from_slice(&nonce);
-    let plaintext = cipher.decrypt(&nonce, ciphertext.as_ref()).map_err(|e| anyhow::anyhow!(e))?;
+    let plaintext = open(&cipher, &nonce, &ciphertext)?;
 
     match output {
         "-" => {
@@ -137,6 +131,31 @@ pub fn process_decrypt(input: &str, key: &str, output: &str) -> Result<()> {
     Ok(())
 }
 
+pub const NONCE_SIZE: usize = 12;
+
+/// A ChaCha20-Poly1305 cipher from a 32 byte key file.
+pub fn load_cipher(path: impl AsRef<Path>) -> Result<ChaCha20Poly1305> {
+    let key = fs::read(path)?;
+    if key.len() != 32 {
+        anyhow::bail!("The key must be 32 bytes, not {}", key.len());
+    }
+    Ok(ChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&key)))
+}
+
+/// Encrypt `plaintext` under a fresh random nonce, returned along with the ciphertext.
+pub fn seal(cipher: &ChaCha20Poly1305, plaintext: &[u8]) -> Result<(Nonce, Vec<u8>)> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, plaintext).map_err(|e| anyhow::anyhow!(e))?;
+    Ok((nonce, ciphertext))
+}
+
+pub fn open(cipher: &ChaCha20Poly1305, nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    if nonce.len() != NONCE_SIZE {
+        anyhow::bail!("The nonce must be {} bytes, not {}", NONCE_SIZE, nonce.len());
+    }
+    cipher.decrypt(Nonce::from_slice(nonce), ciphertext).map_err(|e| anyhow::anyhow!(e))
+}
+
 impl TextSign for Blake3 {
     fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
         let mut buf = Vec::new();
@@ -218,7 +237,9 @@ impl Blake3 {
     }
 
     pub fn try_new(key: &[u8]) -> Result<Self> {
-        let key = &key[..32];
+        let key = key
+            .get(..32)
+            .ok_or_else(|| anyhow::anyhow!("The key must be at least 32 bytes, not {}", key.len()))?;
         let key = key.try_into()?;
         let signer = Blake3::new(key);
         Ok(signer)
@@ -1,4 +1,7 @@
-use std::{fs::File, io::Read};
+use std::{fs::File, io::{BufRead, BufReader, Read, Write}};
+
+/// Extensions of the compressed inputs `decompress` understands.
+pub const COMPRESSED_EXTENSIONS: &[&str] = &["gz", "zst", "bz2", "xz"];
 
 pub fn read_input(input: &str) -> Result<Box<dyn Read>, anyhow::Error> {
     let reader: Box<dyn Read> = if input == "-" {
@@ -7,4 +10,93 @@ pub fn read_input(input: &str) -> Result<Box<dyn Read>, anyhow::Error> {
         Box::new(File::open(input)?)
     };
     Ok(reader)
-}
\ No newline at end of file
+}
+
+/// Decompress `reader` when it starts with the magic bytes of a known compression:
+/// gzip, zstd, bzip2 or xz. Going by the content, it works for stdin too.
+pub fn decompress(reader: Box<dyn Read>) -> Result<Box<dyn Read>, anyhow::Error> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf()?;
+    let reader: Box<dyn Read> = if magic.starts_with(&[0x1f, 0x8b]) {
+        Box::new(flate2::bufread::MultiGzDecoder::new(reader))
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(zstd::Decoder::with_buffer(reader)?)
+    } else if magic.starts_with(b"BZh") {
+        Box::new(bzip2::bufread::MultiBzDecoder::new(reader))
+    } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader))
+    } else {
+        Box::new(reader)
+    };
+    Ok(reader)
+}
+
+pub fn write_output(output: &str) -> Result<Box<dyn Write>, anyhow::Error> {
+    let writer: Box<dyn Write> = if output == "-" {
+        Box::new(std::io::stdout())
+    } else {
+        Box::new(File::create(output)?)
+    };
+    Ok(writer)
+}
+
+/// A directory of its own under the system temp dir for a test's files, removed on drop.
+#[cfg(test)]
+pub(crate) struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> Result<Self, anyhow::Error> {
+        static COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let n = COUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("rcli-{}-{}-{}", name, std::process::id(), n));
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self(dir))
+    }
+
+    /// Path of the file `name` in the directory.
+    pub fn path(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_decompress() -> anyhow::Result<()> {
+        let dir = TempDir::new("utils")?;
+        let data = b"Name,Kit Number\nBuffon,77\n";
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(data)?;
+        let mut bz = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bz.write_all(data)?;
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(data)?;
+        let files = [
+            ("players.csv", data.to_vec()),
+            ("players.csv.gz", gz.finish()?),
+            ("players.csv.zst", zstd::encode_all(&data[..], 0)?),
+            ("players.csv.bz2", bz.finish()?),
+            ("players.csv.xz", xz.finish()?),
+        ];
+        for (name, content) in files {
+            let path = dir.path(name);
+            std::fs::write(&path, content)?;
+            let mut buf = Vec::new();
+            decompress(read_input(&path)?)?.read_to_end(&mut buf)?;
+            assert_eq!(buf, data, "{}", name);
+        }
+        Ok(())
+    }
+}